@@ -0,0 +1,80 @@
+use chrono::{SecondsFormat, Utc};
+use serde_json::json;
+use std::{fs::OpenOptions, io::Write, path::Path, time::Duration};
+
+/// ## Audit Entry
+/// One API request, appended as a JSON line to the `--audit-log` file.
+/// Credentials are sent in headers and never recorded.
+pub struct AuditEntry<'a> {
+    pub endpoint: &'a str,
+    /// HTTP status, `None` when no response was received
+    pub status: Option<u16>,
+    pub bytes: usize,
+    pub duration: Duration,
+    pub error: Option<String>,
+}
+
+impl AuditEntry<'_> {
+    fn to_json_line(&self) -> String {
+        json!({
+            "bytes": self.bytes,
+            "duration_ms": self.duration.as_millis() as u64,
+            "endpoint": self.endpoint,
+            "error": self.error,
+            "status": self.status,
+            "timestamp": Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true),
+        })
+        .to_string()
+    }
+}
+
+/// Appends the entry to the audit log, warning on stderr if it cannot be written
+pub fn append(path: Option<&Path>, entry: &AuditEntry) {
+    let Some(path) = path else {
+        return;
+    };
+    let result = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .and_then(|mut file| writeln!(file, "{}", entry.to_json_line()));
+    if let Err(e) = result {
+        eprintln!("Failed to write audit log {}: {}", path.display(), e);
+    }
+}
+
+#[cfg(test)]
+mod audit_tests {
+    use super::*;
+    use serde_json::Value;
+
+    #[test]
+    fn test_append() {
+        let path = std::env::temp_dir().join(format!("r2logs-audit-{}.log", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        for status in [Some(200), None] {
+            let entry = AuditEntry {
+                endpoint: "https://example.com/logs/retrieve?start=a&end=b",
+                status,
+                bytes: 42,
+                duration: Duration::from_millis(120),
+                error: status.is_none().then(|| "connection refused".to_string()),
+            };
+            append(Some(&path), &entry);
+        }
+        let content = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let entries = content
+            .lines()
+            .map(|line| serde_json::from_str::<Value>(line).unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0]["status"], 200);
+        assert_eq!(entries[0]["bytes"], 42);
+        assert_eq!(entries[0]["duration_ms"], 120);
+        assert!(entries[0]["timestamp"].is_string());
+        assert_eq!(entries[1]["status"], Value::Null);
+        assert_eq!(entries[1]["error"], "connection refused");
+    }
+}
@@ -1,34 +1,62 @@
-use std::env;
+use std::{
+    env,
+    path::{Path, PathBuf},
+};
+
+use crate::error::Error;
+use crate::paths;
 
 /// ## Environment Variables
-/// - `CLOUDFLARE_API_KEY`: Cloudflare API key
+/// - `CLOUDFLARE_API_TOKEN`: Cloudflare API token, used instead of `CLOUDFLARE_API_KEY` when both are set
+/// - `CLOUDFLARE_API_KEY`: Cloudflare API key, required unless `CLOUDFLARE_API_TOKEN` is set
 /// - `R2_ACCESS_KEY_ID`: R2 Access Key ID
 /// - `R2_SECRET_ACCESS_KEY`: R2 Secret Access Key
 /// - `CLOUDFLARE_ACCOUNT_ID`: Cloudflare Account ID
 /// - `BUCKET_NAME`: Bucket name
+/// - `R2LOGS_DEFAULT_LOOKBACK`: (optional) Lookback when no start time is given, e.g. 15m
+///
+/// With `--account-id <ID>` each variable is first read from its profile, `<VAR>_<ID>`
+/// (upper-cased, `-` replaced with `_`), falling back to `<VAR>`.
+/// The variables may also be set in a `.env` file, see [`load_env_file`].
+#[derive(Clone)]
 pub struct UrlEnv {
+    /// Cloudflare API base URL, only pointed elsewhere by tests
+    pub api_base_url: String,
     pub cf_account_id: String,
     pub bucket_name: String,
 }
 
+/// Cloudflare API v4 base URL
+pub const CLOUDFLARE_API_BASE_URL: &str = "https://api.cloudflare.com/client/v4";
+
 pub trait Env {
-    fn get_env() -> Self
+    /// The default environment
+    fn get_env() -> Result<Self, Error>
     where
         Self: std::marker::Sized,
     {
-        match Self::new() {
-            Ok(env) => env,
-            Err(e) => {
-                eprintln!("{}", e);
-                println!();
-                eprintln!("Please set environment variables");
-                std::process::exit(1);
-            }
-        }
+        Self::from_profile(None)
     }
-    fn new() -> Result<Self, String>
+    /// The environment of the `--account-id` profile, or the default one for `None`
+    fn from_profile(account_id: Option<&str>) -> Result<Self, Error>
     where
         Self: Sized;
+    /// Reads `<VAR>_<ACCOUNT_ID>` for a profile, falling back to `<VAR>`
+    fn get_profile_var_or_default(
+        var_name: &str,
+        account_id: Option<&str>,
+        error_vec: &mut Vec<String>,
+    ) -> String {
+        account_id
+            .and_then(|id| env::var(profile_var_name(var_name, id)).ok())
+            .unwrap_or_else(|| Self::get_env_var_or_default(var_name, error_vec))
+    }
+    /// Like `get_profile_var_or_default`, `None` when neither variable is set
+    fn get_profile_var(var_name: &str, account_id: Option<&str>) -> Option<String> {
+        account_id
+            .and_then(|id| env::var(profile_var_name(var_name, id)).ok())
+            .or_else(|| env::var(var_name).ok())
+    }
     fn get_env_var_or_default(var_name: &str, error_vec: &mut Vec<String>) -> String {
         env::var(var_name).unwrap_or_else(|_| {
             error_vec.push(format!("{} is not set", var_name));
@@ -37,22 +65,153 @@ pub trait Env {
     }
 }
 
-impl Env for UrlEnv {
-    fn new() -> Result<Self, String> {
-        let mut error_messages = Vec::<String>::new();
+/// The profile variable of `var_name` for an account, e.g. `BUCKET_NAME_0A1B` for `0a1b`
+pub fn profile_var_name(var_name: &str, account_id: &str) -> String {
+    format!(
+        "{}_{}",
+        var_name,
+        account_id.to_uppercase().replace('-', "_")
+    )
+}
+
+/// Masks an identifier too short for [`mask_middle`], keeping its first four characters, e.g. `0a1b****`
+fn mask(value: &str) -> String {
+    match value.char_indices().nth(4) {
+        Some((end, _)) => format!("{}****", &value[..end]),
+        None => "****".to_string(),
+    }
+}
 
-        let cf_account_id =
-            Self::get_env_var_or_default("CLOUDFLARE_ACCOUNT_ID", &mut error_messages);
-        let bucket_name = Self::get_env_var_or_default("BUCKET_NAME", &mut error_messages);
+/// Query parameters whose values are never printed, matched as part of the name
+const SECRET_PARAMS: [&str; 6] = ["key", "token", "secret", "signature", "password", "auth"];
 
-        if !error_messages.is_empty() {
-            return Err(error_messages.join("\n"));
+/// Redacts an endpoint for verbose output: the middle of the account ID is replaced with asterisks,
+/// e.g. `0a1b****9f8e`, and the values of query parameters resembling keys with `****`
+pub fn redact_endpoint(endpoint: &str) -> String {
+    let (path, query) = match endpoint.split_once('?') {
+        Some((path, query)) => (path, Some(query)),
+        None => (endpoint, None),
+    };
+    let path = match path.split_once("/accounts/") {
+        Some((base, rest)) => {
+            let (account_id, rest) = rest.split_at(rest.find('/').unwrap_or(rest.len()));
+            format!("{}/accounts/{}{}", base, mask_middle(account_id), rest)
         }
-
-        Ok(Self {
-            cf_account_id,
-            bucket_name,
+        None => path.to_string(),
+    };
+    let Some(query) = query else {
+        return path;
+    };
+    let query = query
+        .split('&')
+        .map(|param| match param.split_once('=') {
+            Some((name, _))
+                if SECRET_PARAMS
+                    .iter()
+                    .any(|secret| name.to_lowercase().contains(secret)) =>
+            {
+                format!("{}=****", name)
+            }
+            _ => param.to_string(),
         })
+        .collect::<Vec<_>>()
+        .join("&");
+    format!("{}?{}", path, query)
+}
+
+/// Masks the middle of an identifier, keeping its first and last four characters, e.g. `0a1b****9f8e`.
+/// Shorter identifiers are masked like [`mask`]. Used wherever an account ID is printed.
+pub fn mask_middle(value: &str) -> String {
+    let chars = value.chars().collect::<Vec<_>>();
+    if chars.len() < 12 {
+        return mask(value);
+    }
+    format!(
+        "{}****{}",
+        chars[..4].iter().collect::<String>(),
+        chars[chars.len() - 4..].iter().collect::<String>()
+    )
+}
+
+/// File name searched by [`find_env_file`]
+const ENV_FILE_NAME: &str = ".env";
+
+/// The nearest `.env` from `start` up to the `home` directory.
+/// Outside the home directory only `start` itself is searched.
+pub fn find_env_file(start: &Path, home: Option<&Path>) -> Option<PathBuf> {
+    let home = home.filter(|home| start.starts_with(home));
+    start
+        .ancestors()
+        .take_while(|dir| *dir == start || home.is_some_and(|home| dir.starts_with(home)))
+        .map(|dir| dir.join(ENV_FILE_NAME))
+        .find(|path| path.is_file())
+}
+
+/// Loads the variables of `--env-file`, or of the nearest `.env` found by [`find_env_file`], as a convenience.
+/// Variables already set in the environment win over the file. Returns the file loaded, if any.
+pub fn load_env_file(path: Option<&Path>) -> Result<Option<PathBuf>, Error> {
+    let path = match path {
+        Some(path) => path.to_path_buf(),
+        None => {
+            let found = env::current_dir()
+                .ok()
+                .and_then(|dir| find_env_file(&dir, paths::home_dir().as_deref()));
+            match found {
+                Some(path) => path,
+                None => return Ok(None),
+            }
+        }
+    };
+    dotenvy::from_path(&path).map_err(|e| Error::EnvFile(path.clone(), e))?;
+    Ok(Some(path))
+}
+
+impl UrlEnv {
+    /// The environment of a profile with the bucket of `--bucket` (or the query file) when given,
+    /// in which case `BUCKET_NAME` is not required
+    pub fn with_bucket(account_id: Option<&str>, bucket: Option<&str>) -> Result<Self, Error> {
+        Self::from_vars(account_id, bucket, |name| env::var(name).ok())
+    }
+
+    /// Like [`UrlEnv::with_bucket`], reading the variables with `var` instead of from the process environment
+    fn from_vars(
+        account_id: Option<&str>,
+        bucket: Option<&str>,
+        var: impl Fn(&str) -> Option<String>,
+    ) -> Result<Self, Error> {
+        let cf_account_id = account_id
+            .map(String::from)
+            .or_else(|| var("CLOUDFLARE_ACCOUNT_ID"));
+        let bucket_name = bucket.map(String::from).or_else(|| {
+            account_id
+                .and_then(|id| var(&profile_var_name("BUCKET_NAME", id)))
+                .or_else(|| var("BUCKET_NAME"))
+        });
+
+        match (cf_account_id, bucket_name) {
+            (Some(cf_account_id), Some(bucket_name)) => Ok(Self {
+                api_base_url: CLOUDFLARE_API_BASE_URL.to_string(),
+                cf_account_id,
+                bucket_name,
+            }),
+            (cf_account_id, bucket_name) => {
+                let error_messages = [
+                    ("CLOUDFLARE_ACCOUNT_ID", cf_account_id.is_none()),
+                    ("BUCKET_NAME", bucket_name.is_none()),
+                ]
+                .into_iter()
+                .filter(|(_, missing)| *missing)
+                .map(|(name, _)| format!("{} is not set", name))
+                .collect();
+                Err(Error::MissingEnv(error_messages))
+            }
+        }
+    }
+}
+
+impl Env for UrlEnv {
+    fn from_profile(account_id: Option<&str>) -> Result<Self, Error> {
+        Self::with_bucket(account_id, None)
     }
 }
 
@@ -75,4 +234,96 @@ mod env_tests {
         assert_eq!(var, var_value);
         assert_eq!(error_messages.len(), 1);
     }
+
+    #[test]
+    fn test_find_env_file() {
+        let home = env::temp_dir().join(format!("r2logs-env-{}", std::process::id()));
+        let project = home.join("project/src");
+        std::fs::create_dir_all(&project).unwrap();
+        assert_eq!(find_env_file(&project, Some(&home)), None);
+
+        std::fs::write(home.join(ENV_FILE_NAME), "BUCKET_NAME=home-bucket\n").unwrap();
+        assert_eq!(
+            find_env_file(&project, Some(&home)),
+            Some(home.join(ENV_FILE_NAME))
+        );
+        std::fs::write(home.join("project").join(ENV_FILE_NAME), "").unwrap();
+        assert_eq!(
+            find_env_file(&project, Some(&home)),
+            Some(home.join("project").join(ENV_FILE_NAME))
+        );
+        // outside the home directory, parents are not searched
+        assert_eq!(
+            find_env_file(&project, Some(Path::new("/nonexistent"))),
+            None
+        );
+
+        env::set_var("R2LOGS_ENV_FILE_TEST", "from-environment");
+        std::fs::write(
+            home.join(ENV_FILE_NAME),
+            "R2LOGS_ENV_FILE_TEST=from-file\nR2LOGS_ENV_FILE_ONLY=from-file\n",
+        )
+        .unwrap();
+        let loaded = load_env_file(Some(&home.join(ENV_FILE_NAME))).unwrap();
+        assert_eq!(loaded, Some(home.join(ENV_FILE_NAME)));
+        assert_eq!(
+            env::var("R2LOGS_ENV_FILE_TEST").unwrap(),
+            "from-environment"
+        );
+        assert_eq!(env::var("R2LOGS_ENV_FILE_ONLY").unwrap(), "from-file");
+        assert!(load_env_file(Some(&home.join("missing.env"))).is_err());
+        std::fs::remove_dir_all(&home).unwrap();
+    }
+
+    #[test]
+    fn test_mask() {
+        assert_eq!(mask("0a1b2c3d4e5f"), "0a1b****");
+        assert_eq!(mask("0a1b"), "****");
+        assert_eq!(mask(""), "****");
+    }
+
+    #[test]
+    fn test_redact_endpoint() {
+        assert_eq!(mask_middle("0a1b2c3d4e5f6a7b"), "0a1b****6a7b");
+        assert_eq!(mask_middle("0a1b2c3d"), "0a1b****");
+        assert_eq!(
+            redact_endpoint(
+                "https://api.cloudflare.com/client/v4/accounts/0a1b2c3d4e5f6a7b/logs/retrieve?start=2024-01-11T15:00:00Z&bucket=logs&access_token=abc&X-Amz-Signature=def"
+            ),
+            "https://api.cloudflare.com/client/v4/accounts/0a1b****6a7b/logs/retrieve?start=2024-01-11T15:00:00Z&bucket=logs&access_token=****&X-Amz-Signature=****"
+        );
+        assert_eq!(
+            redact_endpoint("http://127.0.0.1:1234/accounts/0a1b2c3d4e5f6a7b"),
+            "http://127.0.0.1:1234/accounts/0a1b****6a7b"
+        );
+        assert_eq!(
+            redact_endpoint("http://127.0.0.1:1234/"),
+            "http://127.0.0.1:1234/"
+        );
+    }
+
+    #[test]
+    fn test_profile_env() {
+        // the variables are looked up in a map, as the process environment is shared by the tests
+        let vars = std::collections::HashMap::from([
+            ("BUCKET_NAME", "shared-bucket"),
+            ("BUCKET_NAME_ACCOUNT_B", "bucket-b"),
+        ]);
+        let var = |name: &str| vars.get(name).map(|value| value.to_string());
+        let env = UrlEnv::from_vars(Some("account-a"), None, var).unwrap();
+        assert_eq!(env.cf_account_id, "account-a");
+        assert_eq!(env.bucket_name, "shared-bucket");
+        let env = UrlEnv::from_vars(Some("account-b"), None, var).unwrap();
+        assert_eq!(env.cf_account_id, "account-b");
+        assert_eq!(env.bucket_name, "bucket-b");
+        let env = UrlEnv::from_vars(Some("account-b"), Some("override"), var).unwrap();
+        assert_eq!(env.bucket_name, "override");
+        let Err(e) = UrlEnv::from_vars(None, None, |_| None) else {
+            panic!("the variables are required");
+        };
+        assert_eq!(
+            e.to_string(),
+            "CLOUDFLARE_ACCOUNT_ID is not set\nBUCKET_NAME is not set\n\nPlease set environment variables"
+        );
+    }
 }
@@ -0,0 +1,249 @@
+use semver::Version;
+use serde::Deserialize;
+use std::{collections::BTreeMap, env, fs, io, path::Path};
+
+use crate::error::Error;
+
+/// ## Config File
+/// Settings read from `config.toml` in the configuration directory (see `paths`), or from `--config`.
+/// A missing default file is the same as an empty one.
+/// The top-level credentials are the `default` profile, others are `[profiles.<name>]` sections
+/// selected with `--profile`, see [`ConfigFile::credentials`].
+///
+/// ```toml
+/// min_version = "0.3.0"
+/// account_id = "0a1b2c3d"
+/// bucket_name = "worker-logs"
+/// api_token = "..."
+/// r2_access_key_id = "..."
+/// r2_secret_access_key = "..."
+///
+/// [profiles.staging]
+/// account_id = "4e5f6a7b"
+/// bucket_name = "staging-logs"
+/// ```
+#[derive(Deserialize, Debug, Default, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct ConfigFile {
+    /// Oldest r2logs version allowed to run with this configuration
+    pub min_version: Option<Version>,
+    /// `CLOUDFLARE_ACCOUNT_ID`
+    pub account_id: Option<String>,
+    /// `BUCKET_NAME`
+    pub bucket_name: Option<String>,
+    /// `CLOUDFLARE_API_TOKEN`
+    pub api_token: Option<String>,
+    /// `R2_ACCESS_KEY_ID`
+    pub r2_access_key_id: Option<String>,
+    /// `R2_SECRET_ACCESS_KEY`
+    pub r2_secret_access_key: Option<String>,
+    /// Named profiles, e.g. `[profiles.prod]`
+    #[serde(default)]
+    pub profiles: BTreeMap<String, Credentials>,
+}
+
+/// Name of the profile made of the top-level credentials
+pub const DEFAULT_PROFILE: &str = "default";
+
+/// ## Credentials
+/// The credentials of a profile of the config file, each for the environment variable in its comment.
+#[derive(Deserialize, Debug, Default, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct Credentials {
+    /// `CLOUDFLARE_ACCOUNT_ID`
+    pub account_id: Option<String>,
+    /// `BUCKET_NAME`
+    pub bucket_name: Option<String>,
+    /// `CLOUDFLARE_API_TOKEN`
+    pub api_token: Option<String>,
+    /// `R2_ACCESS_KEY_ID`
+    pub r2_access_key_id: Option<String>,
+    /// `R2_SECRET_ACCESS_KEY`
+    pub r2_secret_access_key: Option<String>,
+}
+
+impl ConfigFile {
+    /// Reads the config file at `path`, the default settings when it does not exist
+    pub fn load(path: &Path) -> Result<Self, Error> {
+        match fs::read_to_string(path) {
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Self::default()),
+            text => Self::parse(path, text),
+        }
+    }
+
+    /// Reads the config file at `path` given by `--config`, which must exist
+    pub fn load_required(path: &Path) -> Result<Self, Error> {
+        Self::parse(path, fs::read_to_string(path))
+    }
+
+    fn parse(path: &Path, text: io::Result<String>) -> Result<Self, Error> {
+        let text = text.map_err(|e| {
+            Error::Config(format!("cannot read config file {}: {}", path.display(), e))
+        })?;
+        toml::from_str(&text)
+            .map_err(|e| Error::Config(format!("invalid config file {}: {}", path.display(), e)))
+    }
+
+    /// The credentials of the `--profile`: a `[profiles.<name>]` section, or the top-level ones for
+    /// `default` (or no `--profile`). Fails with the available profiles when there is no such section.
+    pub fn credentials(&self, profile: Option<&str>) -> Result<Credentials, Error> {
+        let name = profile.unwrap_or(DEFAULT_PROFILE);
+        if let Some(credentials) = self.profiles.get(name) {
+            return Ok(credentials.clone());
+        }
+        if name != DEFAULT_PROFILE {
+            let available = [DEFAULT_PROFILE]
+                .into_iter()
+                .chain(self.profiles.keys().map(String::as_str))
+                .collect::<Vec<_>>();
+            return Err(Error::Config(format!(
+                "profile '{}' is not defined in the config file, available profiles: {}",
+                name,
+                available.join(", ")
+            )));
+        }
+        Ok(Credentials {
+            account_id: self.account_id.clone(),
+            bucket_name: self.bucket_name.clone(),
+            api_token: self.api_token.clone(),
+            r2_access_key_id: self.r2_access_key_id.clone(),
+            r2_secret_access_key: self.r2_secret_access_key.clone(),
+        })
+    }
+
+    /// Fails when the running version, `current`, is older than `min_version`
+    pub fn check_min_version(&self, current: &str) -> Result<(), Error> {
+        let Some(required) = &self.min_version else {
+            return Ok(());
+        };
+        let current = Version::parse(current).map_err(|e| Error::Config(e.to_string()))?;
+        if current < *required {
+            return Err(Error::OutdatedVersion {
+                required: required.clone(),
+                current,
+            });
+        }
+        Ok(())
+    }
+}
+
+impl Credentials {
+    /// The environment variables to set from the credentials, skipping those for which `is_set` is true.
+    /// The API token is skipped when either `CLOUDFLARE_API_TOKEN` or `CLOUDFLARE_API_KEY` is set.
+    fn env_vars(&self, is_set: impl Fn(&str) -> bool) -> Vec<(&'static str, &str)> {
+        let api_key_set = is_set("CLOUDFLARE_API_TOKEN") || is_set("CLOUDFLARE_API_KEY");
+        [
+            ("CLOUDFLARE_ACCOUNT_ID", &self.account_id),
+            ("BUCKET_NAME", &self.bucket_name),
+            ("CLOUDFLARE_API_TOKEN", &self.api_token),
+            ("R2_ACCESS_KEY_ID", &self.r2_access_key_id),
+            ("R2_SECRET_ACCESS_KEY", &self.r2_secret_access_key),
+        ]
+        .into_iter()
+        .filter(|(name, _)| match *name {
+            "CLOUDFLARE_API_TOKEN" => !api_key_set,
+            name => !is_set(name),
+        })
+        .filter_map(|(name, value)| Some((name, value.as_deref()?)))
+        .collect()
+    }
+
+    /// Sets the credentials as environment variables. Like a `.env` file, variables already set
+    /// in the environment (or by the `.env` file) win, unless `overwrite`, for an explicit `--profile`.
+    pub fn apply_env(&self, overwrite: bool) {
+        for (name, value) in self.env_vars(|name| !overwrite && env::var_os(name).is_some()) {
+            env::set_var(name, value);
+        }
+    }
+}
+
+#[cfg(test)]
+mod config_file_tests {
+    use super::*;
+
+    #[test]
+    fn test_check_min_version() {
+        let config = toml::from_str::<ConfigFile>("min_version = \"0.4.0\"").unwrap();
+        let error = config.check_min_version("0.3.9").unwrap_err();
+        assert!(matches!(error, Error::OutdatedVersion { .. }));
+        assert_eq!(
+            error.to_string(),
+            "r2logs 0.3.9 is older than the min_version 0.4.0 of the config file, please upgrade"
+        );
+        assert!(config.check_min_version("0.4.0").is_ok());
+        assert!(config.check_min_version("1.0.0").is_ok());
+        assert!(ConfigFile::default().check_min_version("0.1.0").is_ok());
+        assert!(toml::from_str::<ConfigFile>("min_version = \"latest\"").is_err());
+    }
+
+    #[test]
+    fn test_load_missing_config_file() {
+        let path = Path::new("/nonexistent/r2logs/config.toml");
+        assert_eq!(ConfigFile::load(path).unwrap(), ConfigFile::default());
+        assert!(ConfigFile::load_required(path).is_err());
+    }
+
+    #[test]
+    fn test_credentials() {
+        let path = std::env::temp_dir().join(format!("r2logs-config-{}.toml", std::process::id()));
+        fs::write(
+            &path,
+            "account_id = \"0a1b\"\nbucket_name = \"worker-logs\"\napi_token = \"token\"\n",
+        )
+        .unwrap();
+        let config = ConfigFile::load_required(&path).unwrap();
+        assert_eq!(config.account_id.as_deref(), Some("0a1b"));
+        let config = config.credentials(None).unwrap();
+        assert_eq!(
+            config.env_vars(|_| false),
+            [
+                ("CLOUDFLARE_ACCOUNT_ID", "0a1b"),
+                ("BUCKET_NAME", "worker-logs"),
+                ("CLOUDFLARE_API_TOKEN", "token")
+            ]
+        );
+        // the environment wins, and a global API key is not shadowed by the token of the file
+        assert_eq!(
+            config.env_vars(|name| ["BUCKET_NAME", "CLOUDFLARE_API_KEY"].contains(&name)),
+            [("CLOUDFLARE_ACCOUNT_ID", "0a1b")]
+        );
+
+        fs::write(&path, "account_id = 1\n").unwrap();
+        let error = ConfigFile::load_required(&path).unwrap_err().to_string();
+        assert!(error.starts_with(&format!("invalid config file {}", path.display())));
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_profiles() {
+        let config = toml::from_str::<ConfigFile>(
+            r#"
+            account_id = "default-account"
+
+            [profiles.prod]
+            account_id = "prod-account"
+            bucket_name = "prod-logs"
+
+            [profiles.staging]
+            account_id = "staging-account"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(
+            config.credentials(None).unwrap().account_id.as_deref(),
+            Some("default-account")
+        );
+        assert_eq!(
+            config.credentials(Some("default")).unwrap(),
+            config.credentials(None).unwrap()
+        );
+        let prod = config.credentials(Some("prod")).unwrap();
+        assert_eq!(prod.account_id.as_deref(), Some("prod-account"));
+        assert_eq!(prod.bucket_name.as_deref(), Some("prod-logs"));
+        assert_eq!(
+            config.credentials(Some("dev")).unwrap_err().to_string(),
+            "profile 'dev' is not defined in the config file, available profiles: default, prod, staging"
+        );
+        assert!(toml::from_str::<ConfigFile>("[profiles.prod]\nbucket = \"logs\"").is_err());
+    }
+}
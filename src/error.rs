@@ -0,0 +1,189 @@
+use semver::Version;
+use serde_json::json;
+use std::{fmt, io::Write, path::PathBuf};
+
+use crate::commands::ErrorFormat;
+
+/// ## Errors
+/// Failures that end a run. `main` prints them with [`Error::report`] and exits with code 1.
+pub enum Error {
+    /// Required environment variables are not set, one message per variable
+    MissingEnv(Vec<String>),
+    /// A request could not be sent or its body could not be read
+    Http(reqwest::Error),
+    /// The API rejected a request that had to succeed, e.g. the `--preflight` probe
+    Api { status: u16, body: String },
+    /// The time range is inverted or longer than the Logs Engine retention
+    InvalidTimeRange(String),
+    /// The `.env` file could not be read or parsed
+    EnvFile(PathBuf, dotenvy::Error),
+    /// The config file could not be read or parsed
+    Config(String),
+    /// The running version is older than the `min_version` of the config file
+    OutdatedVersion { required: Version, current: Version },
+    /// `--auto-account` found no account, or several, for the API token
+    AccountLookup(String),
+    /// A successful response of another media type than `--expect-content-type`, e.g. an HTML error page
+    UnexpectedContentType {
+        content_type: String,
+        expected: String,
+        snippet: String,
+    },
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::MissingEnv(messages) => write!(
+                f,
+                "{}\n\nPlease set environment variables",
+                messages.join("\n")
+            ),
+            Self::Http(e) if e.is_timeout() => {
+                write!(
+                    f,
+                    "Request timed out, raise --timeout or set 0 to wait indefinitely: {}",
+                    e
+                )
+            }
+            Self::Http(e) => write!(f, "Request failed: {}", e),
+            Self::Api { status, body } => {
+                write!(
+                    f,
+                    "Request rejected with status {}\nError Detail: {}",
+                    status, body
+                )
+            }
+            Self::InvalidTimeRange(message) => write!(f, "{}", message),
+            Self::EnvFile(path, e) => write!(f, "Failed to load {}: {}", path.display(), e),
+            Self::Config(message) => write!(f, "{}", message),
+            Self::OutdatedVersion { required, current } => write!(
+                f,
+                "r2logs {} is older than the min_version {} of the config file, please upgrade",
+                current, required
+            ),
+            Self::AccountLookup(message) => write!(f, "{}", message),
+            Self::UnexpectedContentType {
+                content_type,
+                expected,
+                snippet,
+            } => write!(
+                f,
+                "Unexpected content type: '{}' (expected '{}')\nResponse starts with: {}",
+                content_type, expected, snippet
+            ),
+        }
+    }
+}
+
+impl Error {
+    /// Prints the error to `out` as its message, or as a JSON line for `--error-format json`
+    pub fn report(&self, format: ErrorFormat, out: &mut impl Write) {
+        let line = match format {
+            // as printed for an error returned from `main`
+            ErrorFormat::Text => format!("Error: {}", self),
+            ErrorFormat::Json => match self {
+                Self::MissingEnv(messages) => json_report(
+                    "Required environment variables are not set",
+                    None,
+                    Some(&messages.join(", ")),
+                ),
+                Self::Http(e) => json_report(
+                    &self.to_string(),
+                    e.status().map(|status| status.as_u16()),
+                    None,
+                ),
+                Self::Api { status, body } => {
+                    json_report("Request rejected", Some(*status), Some(body))
+                }
+                Self::UnexpectedContentType {
+                    content_type,
+                    expected,
+                    snippet,
+                } => json_report(
+                    &format!(
+                        "Unexpected content type: '{}' (expected '{}')",
+                        content_type, expected
+                    ),
+                    None,
+                    Some(snippet),
+                ),
+                _ => json_report(&self.to_string(), None, None),
+            },
+        };
+        let _ = writeln!(out, "{}", line);
+    }
+}
+
+/// A failure as one JSON line for `--error-format json`, with a null status or detail when there is none
+pub fn json_report(error: &str, status: Option<u16>, detail: Option<&str>) -> String {
+    json!({"detail": detail, "error": error, "status": status}).to_string()
+}
+
+/// The message of `Display`, for errors printed with `{:?}`
+impl fmt::Debug for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(self, f)
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Http(e) => Some(e),
+            Self::EnvFile(_, e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<reqwest::Error> for Error {
+    fn from(e: reqwest::Error) -> Self {
+        Self::Http(e)
+    }
+}
+
+#[cfg(test)]
+mod error_tests {
+    use super::*;
+
+    #[test]
+    fn test_display() {
+        let missing = Error::MissingEnv(vec![
+            "CLOUDFLARE_ACCOUNT_ID is not set".to_string(),
+            "BUCKET_NAME is not set".to_string(),
+        ]);
+        assert_eq!(
+            missing.to_string(),
+            "CLOUDFLARE_ACCOUNT_ID is not set\nBUCKET_NAME is not set\n\nPlease set environment variables"
+        );
+        let api = Error::Api {
+            status: 403,
+            body: "Authentication error".to_string(),
+        };
+        assert_eq!(format!("{:?}", api), api.to_string());
+        assert_eq!(
+            api.to_string(),
+            "Request rejected with status 403\nError Detail: Authentication error"
+        );
+    }
+
+    #[test]
+    fn test_report() {
+        let api = Error::Api {
+            status: 403,
+            body: "Authentication error".to_string(),
+        };
+        let mut out = Vec::new();
+        api.report(ErrorFormat::Text, &mut out);
+        api.report(ErrorFormat::Json, &mut out);
+        Error::MissingEnv(vec!["BUCKET_NAME is not set".to_string()])
+            .report(ErrorFormat::Json, &mut out);
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "Error: Request rejected with status 403\nError Detail: Authentication error\n\
+             {\"detail\":\"Authentication error\",\"error\":\"Request rejected\",\"status\":403}\n\
+             {\"detail\":\"BUCKET_NAME is not set\",\"error\":\"Required environment variables are not set\",\"status\":null}\n"
+        );
+    }
+}
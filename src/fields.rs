@@ -0,0 +1,113 @@
+use serde_json::{Map, Value};
+
+/// Sets the field at the dotted `path` (e.g. `Event.Request.URL`), creating intermediate objects
+/// and replacing any non-object value in the way
+pub fn set(record: &mut Value, path: &str, value: Value) {
+    let mut current = record;
+    for key in path.split('.') {
+        if !current.is_object() {
+            *current = Value::Object(Map::new());
+        }
+        current = current
+            .as_object_mut()
+            .map(|map| map.entry(key).or_insert(Value::Null))
+            .expect("current is an object");
+    }
+    *current = value;
+}
+
+/// The field at the dotted `path`, `None` when any part of it is missing
+pub fn get<'a>(record: &'a Value, path: &str) -> Option<&'a Value> {
+    path.split('.')
+        .try_fold(record, |current, key| current.as_object()?.get(key))
+}
+
+/// Removes the field at the dotted `path`, leaving the record unchanged when any part of it is missing
+pub fn remove(record: &mut Value, path: &str) {
+    let (parent, key) = match path.rsplit_once('.') {
+        Some((parent, key)) => (
+            parent
+                .split('.')
+                .try_fold(record, |current, key| current.as_object_mut()?.get_mut(key)),
+            key,
+        ),
+        None => (Some(record), path),
+    };
+    if let Some(map) = parent.and_then(Value::as_object_mut) {
+        map.shift_remove(key);
+    }
+}
+
+/// A record holding only the fields at the given dotted paths, keeping their nesting.
+/// Missing fields are left out.
+pub fn project(record: &Value, paths: &[String]) -> Value {
+    let mut projected = Value::Object(Map::new());
+    for path in paths {
+        if let Some(value) = get(record, path) {
+            set(&mut projected, path, value.clone());
+        }
+    }
+    projected
+}
+
+#[cfg(test)]
+mod fields_tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_set() {
+        let mut record = json!({"Event": {"RayID": "1"}, "Outcome": "ok"});
+        set(&mut record, "Outcome", json!("exception"));
+        set(&mut record, "Event.Env", json!("prod"));
+        set(&mut record, "Tags.team", json!("logs"));
+        assert_eq!(
+            record,
+            json!({
+                "Event": {"RayID": "1", "Env": "prod"},
+                "Outcome": "exception",
+                "Tags": {"team": "logs"}
+            })
+        );
+        set(&mut record, "Outcome.code", json!(1));
+        assert_eq!(record["Outcome"], json!({"code": 1}));
+    }
+
+    #[test]
+    fn test_get_and_project() {
+        let record = json!({"Event": {"RayID": "1", "Env": "prod"}, "Outcome": "ok"});
+        assert_eq!(get(&record, "Event.RayID"), Some(&json!("1")));
+        assert_eq!(get(&record, "Outcome.code"), None);
+        assert_eq!(get(&record, "Missing"), None);
+        assert_eq!(
+            project(
+                &record,
+                &[
+                    "Event.RayID".to_string(),
+                    "Outcome".to_string(),
+                    "Missing".to_string()
+                ]
+            ),
+            json!({"Event": {"RayID": "1"}, "Outcome": "ok"})
+        );
+    }
+
+    #[test]
+    fn test_remove() {
+        let mut record = json!({
+            "Event": {"RayID": "1", "Env": "prod"},
+            "Logs": [],
+            "Outcome": "ok",
+            "ScriptName": "worker"
+        });
+        remove(&mut record, "Event.RayID");
+        remove(&mut record, "Logs");
+        remove(&mut record, "Outcome.code");
+        remove(&mut record, "Missing.field");
+        // the remaining fields keep their order
+        assert_eq!(
+            record.to_string(),
+            r#"{"Event":{"Env":"prod"},"Outcome":"ok","ScriptName":"worker"}"#
+        );
+    }
+}
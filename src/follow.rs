@@ -0,0 +1,277 @@
+use std::time::{Duration, Instant};
+
+use chrono::{DateTime, SecondsFormat, Utc};
+use reqwest::Client;
+use serde_json::Value;
+
+use crate::api::{self, ApiEnv, FetchOptions, FetchStats};
+use crate::commands::Commands;
+use crate::config::UrlEnv;
+use crate::error::Error;
+use crate::output;
+use crate::ParsedArgs;
+
+/// Time between two polls of `--follow` when `--interval` is not given
+pub const FOLLOW_POLL_INTERVAL: Duration = Duration::from_secs(15);
+
+/// How far each poll reaches back before the end of the previous one, for logs delivered late
+const FOLLOW_OVERLAP: chrono::Duration = chrono::Duration::seconds(30);
+
+/// ## Follow
+/// How `--follow` polls for new logs, and when it stops.
+pub struct Follow {
+    pub poll_interval: Duration,
+    /// Stop once no records arrived for this long, `None` to follow until interrupted
+    pub inactivity_timeout: Option<Duration>,
+}
+
+impl From<&ParsedArgs> for Follow {
+    fn from(args: &ParsedArgs) -> Self {
+        Self {
+            poll_interval: args.interval.unwrap_or(FOLLOW_POLL_INTERVAL),
+            inactivity_timeout: args.inactivity_timeout,
+        }
+    }
+}
+
+/// Retrieves the logs from the start time on, then keeps polling the time passed since the previous poll,
+/// handing each nonempty batch of new records to `emit`. Returns once `--inactivity-timeout` elapsed
+/// without new records, or on Ctrl-C; failed polls are reported and retried at the next interval.
+pub async fn run(
+    client: &Client,
+    args: &ParsedArgs,
+    url_env: &UrlEnv,
+    api_env: &ApiEnv,
+    options: &FetchOptions,
+    follow: &Follow,
+    mut emit: impl FnMut(&str),
+) -> Result<FetchStats, Error> {
+    let mut stats = FetchStats::default();
+    let polled = tokio::select! {
+        result = poll(client, args, url_env, api_env, options, follow, &mut emit, &mut stats) => result,
+        _ = tokio::signal::ctrl_c() => {
+            if args.verbose {
+                println!("Interrupted, stopping");
+            }
+            Ok(())
+        }
+    };
+    polled.map(|_| stats)
+}
+
+#[allow(clippy::too_many_arguments)]
+async fn poll(
+    client: &Client,
+    args: &ParsedArgs,
+    url_env: &UrlEnv,
+    api_env: &ApiEnv,
+    options: &FetchOptions,
+    follow: &Follow,
+    emit: &mut impl FnMut(&str),
+    stats: &mut FetchStats,
+) -> Result<(), Error> {
+    let mut window = args.clone();
+    let mut seen = Vec::new();
+    let mut last_record = Instant::now();
+    loop {
+        let end = Utc::now().to_rfc3339_opts(SecondsFormat::Secs, true);
+        if window_is_open(&window.start_time, &end) {
+            window.end_time = end;
+            let endpoint = Commands::Retrieve.get_endpoint(&window, url_env);
+            let (text, poll_stats) = api::fetch_logs(
+                client,
+                &endpoint,
+                &api_env.cf_api_key,
+                &api_env.r2_access_key_id,
+                &api_env.r2_secret_access_key,
+                options,
+            )
+            .await?;
+            let succeeded = poll_stats.is_success();
+            stats.merge(poll_stats);
+            if succeeded {
+                let text = new_records(&text, &mut seen, output::timestamp_field(args));
+                if !text.is_empty() {
+                    emit(&text);
+                    last_record = Instant::now();
+                }
+                // the next poll overlaps the end of this one, without going back before the start time
+                window.start_time = overlap_start(&args.start_time, &window.end_time);
+                forget_before(&mut seen, &window.start_time);
+            }
+        }
+
+        let wait = match follow.inactivity_timeout {
+            Some(timeout) => match timeout.checked_sub(last_record.elapsed()) {
+                Some(remaining) if !remaining.is_zero() => remaining.min(follow.poll_interval),
+                _ => {
+                    if args.verbose {
+                        println!("No new logs for {:?}, stopping", timeout);
+                    }
+                    return Ok(());
+                }
+            },
+            None => follow.poll_interval,
+        };
+        tokio::time::sleep(wait).await;
+    }
+}
+
+/// The start of the poll after one that ended at `end_time`: `FOLLOW_OVERLAP` earlier, but not before `start_time`
+fn overlap_start(start_time: &str, end_time: &str) -> String {
+    match (
+        DateTime::parse_from_rfc3339(start_time),
+        DateTime::parse_from_rfc3339(end_time),
+    ) {
+        (Ok(start), Ok(end)) => (end - FOLLOW_OVERLAP)
+            .max(start)
+            .to_rfc3339_opts(SecondsFormat::Secs, true),
+        _ => end_time.to_string(),
+    }
+}
+
+/// The records of a poll that were not emitted by the previous ones, remembering them with their timestamp.
+/// Records without a readable timestamp are always new.
+fn new_records(text: &str, seen: &mut Vec<(DateTime<Utc>, String)>, field: &str) -> String {
+    let mut lines = Vec::new();
+    for line in text.lines().filter(|line| !line.trim().is_empty()) {
+        let time = serde_json::from_str::<Value>(line)
+            .ok()
+            .and_then(|record| output::event_time(&record, field));
+        match time {
+            Some(time) if seen.iter().any(|(t, l)| *t == time && l == line) => continue,
+            Some(time) => seen.push((time, line.to_string())),
+            None => {}
+        }
+        lines.push(line);
+    }
+    lines.join("\n")
+}
+
+/// Forgets the records older than the next poll's start, which it cannot return again
+fn forget_before(seen: &mut Vec<(DateTime<Utc>, String)>, start_time: &str) {
+    if let Ok(start) = DateTime::parse_from_rfc3339(start_time) {
+        seen.retain(|(time, _)| *time >= start);
+    }
+}
+
+/// Whether the range has at least one second to poll, as times are sent with a precision of a second
+fn window_is_open(start_time: &str, end_time: &str) -> bool {
+    match (
+        DateTime::parse_from_rfc3339(start_time),
+        DateTime::parse_from_rfc3339(end_time),
+    ) {
+        (Ok(start), Ok(end)) => start < end,
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod follow_tests {
+    use super::*;
+    use mockito::Matcher;
+
+    #[tokio::test]
+    async fn test_follow_stops_after_inactivity() {
+        let mut server = mockito::Server::new_async().await;
+        let data = "{\"Outcome\":\"ok\"}\n";
+        // only the first poll, from the start time, finds records
+        let first_poll = server
+            .mock("GET", "/accounts/account/logs/retrieve")
+            .match_query(Matcher::UrlEncoded(
+                "start".into(),
+                "2024-01-11T15:00:00Z".into(),
+            ))
+            .with_body(data)
+            .expect(1)
+            .create_async()
+            .await;
+        server
+            .mock("GET", "/accounts/account/logs/retrieve")
+            .match_query(Matcher::Any)
+            .create_async()
+            .await;
+        let args = ParsedArgs {
+            start_time: "2024-01-11T15:00:00Z".to_string(),
+            ..Default::default()
+        };
+        let url_env = UrlEnv {
+            api_base_url: server.url(),
+            cf_account_id: "account".to_string(),
+            bucket_name: "bucket".to_string(),
+        };
+        let api_env = ApiEnv {
+            cf_api_key: "cf_api_key".to_string(),
+            r2_access_key_id: "r2_access_key_id".to_string(),
+            r2_secret_access_key: "r2_secret_access_key".to_string(),
+        };
+        let follow = Follow {
+            poll_interval: Duration::from_millis(20),
+            inactivity_timeout: Some(Duration::from_millis(200)),
+        };
+
+        let started = Instant::now();
+        let mut emitted = Vec::new();
+        let stats = run(
+            &Client::new(),
+            &args,
+            &url_env,
+            &api_env,
+            &FetchOptions::default(),
+            &follow,
+            |text| emitted.push(text.to_string()),
+        )
+        .await
+        .unwrap();
+
+        first_poll.assert();
+        assert!(stats.is_success());
+        assert_eq!(emitted, ["{\"Outcome\":\"ok\"}"]);
+        let elapsed = started.elapsed();
+        assert!(elapsed >= Duration::from_millis(200));
+        assert!(elapsed < Duration::from_secs(5));
+    }
+
+    #[test]
+    fn test_overlap_and_new_records() {
+        assert_eq!(
+            overlap_start("2024-01-11T15:00:00Z", "2024-01-11T15:05:00Z"),
+            "2024-01-11T15:04:30Z"
+        );
+        assert_eq!(
+            overlap_start("2024-01-11T15:00:00Z", "2024-01-11T15:00:10Z"),
+            "2024-01-11T15:00:00Z"
+        );
+
+        let first = r#"{"EventTimestampMs":1704985180000,"n":1}"#;
+        let second = r#"{"EventTimestampMs":1704985190000,"n":2}"#;
+        let mut seen = Vec::new();
+        let field = output::DEFAULT_TIMESTAMP_FIELD;
+        assert_eq!(new_records(first, &mut seen, field), first);
+        // the next poll overlaps the first record
+        let text = format!("{}\n{}\nnot json\n", first, second);
+        assert_eq!(
+            new_records(&text, &mut seen, field),
+            format!("{}\nnot json", second)
+        );
+        forget_before(&mut seen, "2024-01-11T14:59:45Z");
+        assert_eq!(seen.len(), 1);
+        assert_eq!(
+            new_records(&text, &mut seen, field),
+            format!("{}\nnot json", first)
+        );
+    }
+
+    #[test]
+    fn test_window_is_open() {
+        assert!(window_is_open(
+            "2024-01-11T15:00:00Z",
+            "2024-01-11T15:00:01Z"
+        ));
+        assert!(!window_is_open(
+            "2024-01-11T15:00:01Z",
+            "2024-01-11T15:00:01Z"
+        ));
+        assert!(!window_is_open("invalid", "2024-01-11T15:00:01Z"));
+    }
+}
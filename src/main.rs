@@ -5,13 +5,17 @@
 //! $ r2logs [OPTIONS] [START_TIME] [END_TIME] [COMMAND]
 //! $ r2logs # retrieve logs from 5 minutes ago to now
 //! $ r2logs list # list relevant R2 objects containing logs
+//! $ r2logs recent 2 # stream the two most recently modified R2 objects
+//! $ r2logs -1h now count # number of log records in the last hour
 //!
 //! # retrieve logs from 2024-01-11T15:00:00Z to 2024-01-11T15:05:00Z
 //! $ r2logs 2024-01-11T15:00:00Z 2024-01-11T15:05:00Z
 //! # list relevant R2 objects containing logs from 2024-01-11T15:00:00Z to 2024-01-11T15:05:00Z
 //! $ r2logs 2024-01-11T15:00:00Z 2024-01-11T15:05:00Z list
+//! # retrieve logs from one hour ago to now
+//! $ r2logs -1h now
 //!
-//! $ r2logs | jq . # pretty print JSON
+//! $ r2logs --pretty # pretty print JSON, or pipe into `jq .`
 //! $ r2logs --help # print help
 //! ```
 //! ## Commands
@@ -20,66 +24,1011 @@
 //!   - Stream logs stored in R2 that match the provided query parameters
 //! - list
 //!   - List relevant R2 objects containing logs matching the provided query parameters
+//! - recent [N]
+//!   - Stream the contents of the N (default 1) most recently modified R2 objects from the last day
+//! - count
+//!   - Print only the number of log records in the time range, after the record options such as --filter-outcome
+//! - validate
+//!   - Check the flags, environment and time range and print the resolved endpoint, without any network call
+//! - completions <bash|elvish|fish|powershell|zsh>
+//!   - Print a shell completion script, e.g. `source <(r2logs completions bash)`; no environment variables are needed
 //! - help
 //!   - Print this message or the help of the given subcommand(s)
 //! ## Options
+//! - --start <START>, --since <START>
+//!   - Named alternative to START_TIME
+//! - --until <END>
+//!   - Named alternative to END_TIME; alone, the range ends here and starts the default lookback earlier
+//!   - The named and positional forms cannot be mixed
+//! - --duration <DURATION>
+//!   - Length of the range from the start time, e.g. 30s, 5m, 2h, 1d (conflicts with END_TIME)
+//! - --query-file <PATH>
+//!   - Read start, end or duration, bucket and output options from a TOML (or .json) file; flags take precedence
+//! - --calendar <hourly|daily>
+//!   - Use the most recent completed period as the time range: the previous full clock hour, or yesterday (UTC)
+//! - --prefix <PREFIX>
+//!   - Object key prefix to query, `{DATE}` is appended unless it already contains it (default: {DATE})
+//! - --literal-prefix
+//!   - Send the --prefix value verbatim without `{DATE}`, e.g. logs/2024-01-11/
+//! - --date-format <FORMAT>
+//!   - Replace `{DATE}` in the prefix with the start date in this strftime format, e.g. `%Y/%m/%d`, instead of the server-side expansion
 //! - -v, --verbose
 //!   - Verbose output, print time range and endpoint
+//!   - While downloading, show the bytes and lines received on a status line of a terminal's stderr
+//!   - After retrieving, print the number of records per outcome to stderr, e.g. `Outcomes: ok: 412, exception: 7`
+//! - -q, --quiet
+//!   - Suppress informational notes on stderr such as "No logs found" and retries; errors still print
+//! - --no-redact
+//!   - Print the endpoint in verbose output as is; by default the middle of the account ID and the values
+//!     of key-like query parameters are masked
+//! - --error-format <text|json>
+//!   - Print failures as text (default) or as one JSON object per line, `{"detail":..,"error":..,"status":..}`;
+//!     the exit codes are the same
+//! - --verbose-json
+//!   - After the logs, print run diagnostics (duration_ms, bytes, lines, retries, status) as JSON to stderr
+//! - --emit-sources <PATH>
+//!   - Write the objects fetched one by one (`recent`, --chronological) with their bytes as a JSON array to this sidecar file
+//! - --report <PATH>
+//!   - Write a JSON summary of the run to this file, even when it fails: exit code, range, bucket, objects,
+//!     bytes, lines (null when streamed), duration, retries and errors
+//! - --timing
+//!   - Print time to first byte, body download and total durations to stderr
+//! - --progress-fd <FD>
+//!   - Write progress as JSON lines (bytes, lines, elapsed_ms) to this inherited file descriptor, e.g. 3 with `3>progress.ndjson` (Unix)
+//! - --retry-on-connect-error <true|false>
+//!   - Retry requests failing with a connection error (default: true)
+//!   - Timeouts are always retried, other transport errors never are
+//! - --max-retries <N>
+//!   - Retry a request rejected with 429, 500, 502, 503 or 504 up to N times (default: 3), honoring `Retry-After`
+//! - --retry-budget <DURATION>
+//!   - Cap the total time spent retrying a single request, e.g. 10s; a retry that would exceed it is not made
+//! - --auth-header-name <NAME>, --auth-scheme <SCHEME>
+//!   - Send the API key as `<NAME>: <SCHEME> <KEY>` (default: `Authorization: Bearer <KEY>`)
+//! - --flatten-logs
+//!   - Emit one record per Worker log entry, carrying the event's RayID and ScriptName
+//! - --preflight
+//!   - Probe the API with a one-minute `list` before retrieving, aborting early on auth or connection failure
+//! - --dry-run
+//!   - Print the endpoint of `retrieve` or `list`, redacted unless `--no-redact`, and exit without a request;
+//!     the environment variables are still required
+//! - --since-latest-object
+//!   - Start right after the most recently modified object of the last day, pulling only newer logs
+//! - --from-stdin-keys
+//!   - Retrieve the objects whose keys are read from stdin, one per line, e.g. `r2logs list --keys-only | r2logs --from-stdin-keys`
+//! - --chronological
+//!   - List the objects in the range and retrieve each, merging all their records into one stream in timestamp order
+//! - -f, --follow
+//!   - Keep polling for new logs every 15 seconds after retrieving the range, until Ctrl-C
+//!   - Each poll looks back 30 seconds for late-delivered logs, skipping the records already printed by their timestamp
+//! - --interval <DURATION>
+//!   - With --follow, the time between two polls, e.g. 1m (default: 15s)
+//! - --inactivity-timeout <DURATION>
+//!   - With --follow, exit (code 0) once no new logs arrived for this long, e.g. 10m
+//! - --circuit-threshold <N>
+//!   - After N consecutive failed requests, skip the remaining objects or accounts with a "circuit open" error
+//! - --partial-ok
+//!   - When fetching several objects or `--chunk-minutes` windows, print the ones that succeeded even if others failed
+//!     (exit code 3); the windows after a rejected one are not fetched
+//! - --audit-log <PATH>
+//!   - Append one JSON line per API request (timestamp, endpoint, status, bytes, duration) to PATH
+//! - --expect-content-type <MEDIA_TYPE>
+//!   - Reject responses with another `Content-Type`, e.g. HTML error pages from a misconfigured endpoint
+//! - --account-id <ID>
+//!   - Fetch from this account instead of `CLOUDFLARE_ACCOUNT_ID` (repeatable); with several accounts,
+//!     records are labeled with an `AccountID` field and lists get a `# <ID>` header each
+//!   - Credentials and bucket come from the account's profile variables, e.g. `CLOUDFLARE_API_KEY_<ID>`,
+//!     falling back to the shared ones
+//! - --auto-account
+//!   - When `CLOUDFLARE_ACCOUNT_ID` is not set, look up the account of the API token; fails if it can access several
+//! - --bucket <NAME>
+//!   - Query this bucket instead of `BUCKET_NAME`, which is then not required
+//! - --proxy <URL>, --proxy-auth <USER:PASS>
+//!   - Send requests through an HTTP(S) proxy, with basic auth from `--proxy-auth` or the URL's userinfo
+//!   - The password is masked in verbose output
+//! - --split-by <day|hour> --split-dir <DIR>
+//!   - Append records to `DIR/YYYY-MM-DD.ndjson` or `DIR/YYYY-MM-DDTHH.ndjson` after their timestamp instead of printing them
+//! - --rotate-bytes <N>
+//!   - With --split-dir, rotate each file into numbered parts of at most N bytes, e.g. `DIR/YYYY-MM-DD.001.ndjson`
+//!   - With --output FILE, write the output to numbered parts of at most N bytes as it streams in,
+//!     e.g. `logs.001.ndjson` and `logs.002.ndjson` for `-o logs.ndjson`
+//! - --sqlite <PATH>
+//!   - Insert the records into the `logs` table of an SQLite database, one column per `--fields` entry or a single `json` column
+//! - --post-command <COMMAND>
+//!   - Pipe the output into COMMAND run by the shell, e.g. `aws s3 cp - s3://bucket/logs.ndjson`; fails if it exits nonzero
+//! - -o, --output <PATH>
+//!   - Write the output to this file instead of stdout, truncating it if it exists; the directory must exist
+//! - --line-buffered
+//!   - Flush the output after every line rather than after each response chunk or `--follow` poll, e.g. for `| grep`
+//! - --separator <TEXT>
+//!   - Print this line between the outputs of several objects (`recent`), accounts or `--follow` polls
+//! - --format <json|table>
+//!   - `table` prints one line per Workers trace event: time, outcome, script name, request method and URL
+//! - --pretty
+//!   - Pretty-print each JSON record over several lines; lines that are not JSON are printed as they are with a warning
+//! - --split-stdout-stderr-by-level
+//!   - Print error-level records (an exception or an error log) to stderr and the others to stdout
+//! - --framing <newline|length-prefixed>
+//!   - How records are delimited: one per line (default) or each as a 4-byte big-endian length followed by its JSON bytes
+//! - --empty-behavior <ok|fail|silent>
+//!   - When nothing was found: `fail` prints a note to stderr and exits with code 2 (default), `ok` prints the note
+//!     and exits 0, `silent` prints nothing and exits 0
+//! - --resolve <HOST:PORT:IP>
+//!   - Connect to IP instead of resolving HOST, e.g. `api.cloudflare.com:443:104.19.192.29` (repeatable)
+//! - --tcp-nodelay[=BOOL]
+//!   - Set (or with false, clear) TCP_NODELAY on the connections; default: the HTTP client's default
+//! - --timeout <SECONDS>
+//!   - Give up on a request after this many seconds, 0 for no timeout (default: 30)
+//! - --chunk-minutes <MINUTES>
+//!   - Retrieve a longer range in windows of this many minutes, one after the other, printing records
+//!     repeated at a window boundary once; 0 for a single request (default: 60)
+//! - --concurrency <N>
+//!   - Retrieve up to N windows at once, at most 8, still printing them in order (default: 1)
+//! - --page-delay <MS>
+//!   - Wait between the pages of a paginated object list (default: 0)
+//! - --warn-bytes <N>
+//!   - Warn once on stderr when more than N bytes were received, suggesting a narrower range; the download continues
+//! - --checksum sha256
+//!   - After the output, print a checksum of the retrieved response bodies to stderr
+//!   - With `--output FILE` (and no `--rotate-bytes`), also write `FILE.sha256` (`<hex>  FILE`, as `sha256sum` does) so `sha256sum -c` verifies the file
+//! - --byte-count-only
+//!   - Print only the number of bytes retrieved, streaming the logs without keeping them
+//! - --list-format <plain|json|table>
+//!   - Output format of the `list` command (default: plain, one object key per line)
+//!   - Without it, `--format json` or `--format table` also applies to `list`, e.g. `r2logs --format json list | jq '.[].key'`
+//! - --humanize-dates
+//!   - With `--list-format table`, show how long ago each object was modified next to its date, e.g. "2 hours ago"
+//! - --keys-only
+//!   - Print only the object keys of the `list` command, one per line, e.g. for `rclone copy --files-from`
+//! - --strip-ansi
+//!   - Remove ANSI escape codes from log messages
+//! - --sanitize
+//!   - Replace control characters other than newline and tab (e.g. NUL, BEL) with `\uXXXX` escapes
+//! - --set-field <PATH=VALUE>
+//!   - Set the dotted field PATH of each record to VALUE, `{env:VAR}` is replaced with $VAR (repeatable)
+//! - --add-bucket <FIELD=GRANULARITY>
+//!   - Add FIELD holding the start of the record's minute, hour or day bucket (repeatable)
+//! - --server-fields <FIELDS>
+//!   - Ask the retrieve endpoint for only these comma-separated fields (`fields` parameter), reducing the transfer
+//! - --fields <FIELDS>
+//!   - Keep only these comma-separated dotted fields of each record, e.g. `EventTimestampMs,Event.RayID`
+//! - --drop-fields <FIELDS>
+//!   - Remove these comma-separated dotted fields from each record, keeping the others
+//! - --field-order <FIELDS>
+//!   - Emit these comma-separated fields first in each record, followed by the others in their original order
+//! - --line-numbers
+//!   - Prefix each printed line with its 1-based index and a tab
+//! - --merge-exceptions
+//!   - Also print the exceptions of the records (name, message, stack) as highlighted blocks to stderr
+//! - --compact-errors
+//!   - With --merge-exceptions, print consecutive identical exceptions once as `<message> (xN)`
+//! - --distinct <PATH>
+//!   - Print only the unique values of this dotted field across the records, one per line, sorted
+//! - --histogram <minute|hour|day>
+//!   - Also print the number of records per minute, hour or day of their timestamp as a text histogram to stderr
+//! - --detect-gaps <SECONDS>
+//!   - Warn on stderr when consecutive records are further apart than this, indicating possible missing logs
+//! - --sort
+//!   - Sort the records oldest first by their timestamp
+//! - --timestamp-field <PATH>
+//!   - Dotted path of the record timestamp (milliseconds or RFC3339) used by `--sort`, `--split-by` and `--add-bucket` (default: EventTimestampMs)
+//! - --canonical
+//!   - Re-serialize each record with object keys sorted recursively, for stable diffs and hashes
+//! - --redact-all-urls
+//!   - Strip the query string and fragment of each `Event.Request.URL`, keeping the path, before output
+//! - --min-timestamp <TIME> / --max-timestamp <TIME>
+//!   - Drop records whose timestamp is before / after this time (inclusive); RFC3339, epoch milliseconds, now or e.g. -30m
+//! - --filter-outcome <OUTCOME>
+//!   - Keep only the trace events with this `Outcome`, e.g. `exception` (repeatable, any matches); non-JSON lines are dropped
+//! - --env-file <PATH>
+//!   - Read the environment variables from this file instead of the nearest `.env`
+//! - --config <PATH>
+//!   - Read the config file from this path instead of `config.toml` in the configuration directory
+//! - --profile <NAME>
+//!   - Use the credentials of the `[profiles.<NAME>]` section of the config file instead of the environment
+//!     variables; `default` is the top-level credentials (default: default)
+//! - --seed <SEED>
+//!   - Seed randomized behaviors (e.g. retry jitter) for reproducible runs
 //! - -h, --help
 //!   - Print help (see a summary with '-h')
 //! - -V, --version
 //!   - Print version
+//! ## Exit Codes
+//! - 0: Logs were found, or the range was empty with `--empty-behavior ok` or `silent`
+//! - 1: Failure, e.g. a rejected token, a missing environment variable or an invalid time range
+//! - 2: The range was valid but no logs were found
+//! - 3: Only some of the objects or windows could be fetched, with `--partial-ok`
 //! ## Environment Variables
-//! - `CLOUDFLARE_API_KEY`: Cloudflare API key
+//! - `CLOUDFLARE_API_TOKEN`: Cloudflare API token, takes precedence over `CLOUDFLARE_API_KEY` when both are set
+//! - `CLOUDFLARE_API_KEY`: Cloudflare API key, required unless `CLOUDFLARE_API_TOKEN` is set
 //! - `R2_ACCESS_KEY_ID`: R2 Access Key ID
 //! - `R2_SECRET_ACCESS_KEY`: R2 Secret Access Key
 //! - `CLOUDFLARE_ACCOUNT_ID`: Cloudflare Account ID
 //! - `BUCKET_NAME`: Bucket name
+//! - `<VAR>_<ACCOUNT_ID>`: (optional) Per-account profile of the variables above, used with `--account-id`
+//! - `R2LOGS_DEFAULT_LOOKBACK`: (optional) Lookback when no start time is given, e.g. 15m (default: 5m)
+//!
+//! As a convenience the variables may be kept in a `.env` file, read from the current directory
+//! or its nearest parent up to the home directory, or from `--env-file`. Variables set in the environment win.
+//! ## Config File
+//! `config.toml` in the configuration directory, e.g. `~/.config/r2logs/config.toml` on Linux, or `--config <PATH>`:
+//! - `min_version`: (optional) Oldest r2logs version allowed to run, e.g. "0.3.0"; older versions exit asking to upgrade
+//! - `account_id`, `bucket_name`, `api_token`, `r2_access_key_id`, `r2_secret_access_key`: (optional) Credentials
+//!   used for `CLOUDFLARE_ACCOUNT_ID`, `BUCKET_NAME`, `CLOUDFLARE_API_TOKEN`, `R2_ACCESS_KEY_ID` and
+//!   `R2_SECRET_ACCESS_KEY` when they are set neither in the environment nor in the `.env` file
+//! - `[profiles.<NAME>]`: (optional) The same credentials for another account, selected with `--profile <NAME>`
 //! ## References
 //! - [Cloudflare Logs Engine](https://developers.cloudflare.com/logs/r2-log-retrieval/)
 //! - [R2](https://developers.cloudflare.com/r2/)
 
 mod api;
+mod audit;
 mod commands;
 mod config;
+mod config_file;
+mod error;
+mod fields;
+mod follow;
+mod merge;
+mod model;
+mod output;
+mod paths;
+mod pipe;
+mod progress;
+mod query;
+mod report;
+mod retry;
+mod rng;
+mod split;
+mod sqlite;
 
-use crate::{api::ApiEnv, config::Env};
-use commands::{Args, Commands};
+use crate::{
+    api::{ApiEnv, FetchOptions, FetchStats, ProxyConfig},
+    config::Env,
+};
+use chrono::{DateTime, Utc};
+use commands::{
+    time_chunks, Args, ChecksumAlgorithm, Commands, EmptyBehavior, ErrorFormat, Framing,
+    Granularity, ListFormat, RecordFormat, SplitBy,
+};
 use config::UrlEnv;
+use config_file::ConfigFile;
+use error::Error;
+use follow::Follow;
+use model::R2Object;
+use output::OutcomeCounts;
+use progress::Progress;
+use report::Report;
+use std::{
+    borrow::Cow,
+    fs::File,
+    io::{self, Write},
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    process::ExitCode,
+    time::Instant,
+};
 
+#[derive(Default, Clone)]
 struct ParsedArgs {
     start_time: String,
     end_time: String,
     verbose: bool,
+    quiet: bool,
+    no_redact: bool,
+    error_format: ErrorFormat,
+    verbose_json: bool,
+    emit_sources: Option<PathBuf>,
+    report: Option<PathBuf>,
+    timing: bool,
+    progress_fd: Option<i32>,
+    retry_on_connect_error: bool,
+    max_retries: u32,
+    retry_budget: Option<std::time::Duration>,
+    auth_header_name: String,
+    auth_scheme: String,
+    flatten_logs: bool,
+    strip_ansi: bool,
+    sanitize: bool,
+    set_fields: Vec<(String, String)>,
+    add_buckets: Vec<(String, Granularity)>,
+    line_numbers: bool,
+    merge_exceptions: bool,
+    compact_errors: bool,
+    sort: bool,
+    distinct: Option<String>,
+    histogram: Option<Granularity>,
+    detect_gaps: Option<u64>,
+    timestamp_field: Option<String>,
+    canonical: bool,
+    redact_urls: bool,
+    min_timestamp: Option<DateTime<Utc>>,
+    max_timestamp: Option<DateTime<Utc>>,
+    filter_outcomes: Vec<String>,
+    server_fields: Vec<String>,
+    prefix: Option<String>,
+    date_format: Option<String>,
+    fields: Vec<String>,
+    drop_fields: Vec<String>,
+    field_order: Vec<String>,
+    seed: Option<u64>,
+    env_file: Option<PathBuf>,
+    config: Option<PathBuf>,
+    profile: Option<String>,
+    preflight: bool,
+    dry_run: bool,
+    since_latest_object: bool,
+    from_stdin_keys: bool,
+    /// Objects read from stdin with `--from-stdin-keys`
+    stdin_objects: Vec<R2Object>,
+    chronological: bool,
+    follow: bool,
+    inactivity_timeout: Option<std::time::Duration>,
+    interval: Option<std::time::Duration>,
+    circuit_threshold: Option<u32>,
+    partial_ok: bool,
+    audit_log: Option<PathBuf>,
+    expect_content_type: Option<String>,
+    bucket: Option<String>,
+    account_ids: Vec<String>,
+    auto_account: bool,
+    proxy: Option<ProxyConfig>,
+    resolve: Vec<(String, SocketAddr)>,
+    tcp_nodelay: Option<bool>,
+    timeout: u64,
+    chunk_minutes: u64,
+    concurrency: u8,
+    page_delay_ms: u64,
+    warn_bytes: Option<usize>,
+    checksum: Option<ChecksumAlgorithm>,
+    byte_count_only: bool,
+    split_by: Option<SplitBy>,
+    split_dir: Option<PathBuf>,
+    rotate_bytes: Option<u64>,
+    post_command: Option<String>,
+    output: Option<PathBuf>,
+    line_buffered: bool,
+    separator: Option<String>,
+    framing: Framing,
+    pretty: bool,
+    split_by_level: bool,
+    format: RecordFormat,
+    sqlite: Option<PathBuf>,
+    empty_behavior: EmptyBehavior,
+    list_format: ListFormat,
+    humanize_dates: bool,
+    keys_only: bool,
     commands: Option<Commands>,
 }
 
+/// Exit code of a failed run, e.g. a rejected token or a missing environment variable
+const EXIT_ERROR: i32 = 1;
+
+/// Exit code when `--partial-ok` emitted the logs of only some of the fetched objects or windows
+const EXIT_PARTIAL: i32 = 3;
+
+/// The exit code of a run where the API rejected the request or some objects could not be fetched,
+/// `None` when all succeeded. Without `--partial-ok` failed objects or windows fail the whole run.
+fn failure_exit_code(stats: &FetchStats, partial_ok: bool) -> Option<i32> {
+    let rejected = stats
+        .status
+        .is_some_and(|status| !(200..300).contains(&status));
+    match (stats.failed.is_empty(), partial_ok) {
+        (true, _) if rejected => Some(EXIT_ERROR),
+        (true, _) => None,
+        (false, true) => Some(EXIT_PARTIAL),
+        (false, false) => Some(EXIT_ERROR),
+    }
+}
+
+/// The environment of each `--account-id` profile, or the default environment of `account_id`
+/// resolved by `--auto-account`, with the bucket of `--bucket` or the query file. Fails when a variable is missing.
+fn load_envs(args: &ParsedArgs, account_id: Option<&str>) -> Result<Vec<(UrlEnv, ApiEnv)>, Error> {
+    let bucket = args.bucket.as_deref();
+    if args.account_ids.is_empty() {
+        return Ok(vec![(
+            UrlEnv::with_bucket(account_id, bucket)?,
+            ApiEnv::get_env()?,
+        )]);
+    }
+    args.account_ids
+        .iter()
+        .map(|id| {
+            Ok((
+                UrlEnv::with_bucket(Some(id), bucket)?,
+                ApiEnv::from_profile(Some(id))?,
+            ))
+        })
+        .collect()
+}
+
+/// Whether several `--account-id`s are fetched, whose outputs are labeled with their account.
+/// A single `--account-id` only overrides `CLOUDFLARE_ACCOUNT_ID`.
+fn labels_accounts(args: &ParsedArgs) -> bool {
+    args.account_ids.len() > 1
+}
+
+/// The account of the API token, for `--auto-account` without `CLOUDFLARE_ACCOUNT_ID`
+async fn resolve_account(args: &ParsedArgs) -> Result<String, Error> {
+    let client = api::build_client(args, &mut io::sink())?;
+    let api_env = ApiEnv::get_env()?;
+    let options = FetchOptions::from(args);
+    let account_id =
+        api::resolve_account_id(&client, config::CLOUDFLARE_API_BASE_URL, &api_env, &options)
+            .await?;
+    if args.verbose {
+        println!("Resolved account {}", config::mask_middle(&account_id));
+    }
+    Ok(account_id)
+}
+
+/// Creates (or truncates) the `--output` file, explaining a missing directory
+fn create_output(path: &Path) -> Result<File, String> {
+    File::create(path).map_err(|e| match path.parent() {
+        Some(dir) if e.kind() == io::ErrorKind::NotFound && !dir.as_os_str().is_empty() => format!(
+            "Cannot write to {}: directory {} does not exist",
+            path.display(),
+            dir.display()
+        ),
+        _ => format!("Cannot write to {}: {}", path.display(), e),
+    })
+}
+
+/// Writes the formatted output in the `--framing` and flushes it
+fn write_output(out: &mut impl Write, text: &str, framing: Framing) -> io::Result<()> {
+    match framing {
+        Framing::Newline => writeln!(out, "{}", text)?,
+        Framing::LengthPrefixed => {
+            output::write_length_prefixed(text, out)?;
+        }
+    }
+    out.flush()
+}
+
+/// Applies `--pretty` or `--format table` to the records
+fn render_records(text: &str, args: &ParsedArgs) -> String {
+    match (args.pretty, args.format) {
+        (true, _) => output::pretty(text, &mut io::stderr()),
+        (false, RecordFormat::Table) => output::format_table(text, &mut io::stderr()),
+        (false, RecordFormat::Json) => text.to_string(),
+    }
+}
+
+/// Whether the logs can be written to stdout as they arrive instead of being buffered:
+/// a `retrieve` of a single account whose records are printed unchanged, its `--chunk-minutes` windows one after the other
+fn can_stream(command: &Commands, args: &ParsedArgs) -> bool {
+    *command == Commands::Retrieve
+        && !labels_accounts(args)
+        && output::is_passthrough(args)
+        && !args.byte_count_only
+        && !args.chronological
+        && !args.since_latest_object
+        && !args.from_stdin_keys
+        && !args.preflight
+        && !args.line_numbers
+        && args.framing == Framing::Newline
+        && !args.pretty
+        && !args.split_by_level
+        && args.filter_outcomes.is_empty()
+        && args.format == RecordFormat::Json
+        && !args.merge_exceptions
+        && args.distinct.is_none()
+        && args.histogram.is_none()
+        && args.detect_gaps.is_none()
+        && args.expect_content_type.is_none()
+        && args.split_by.is_none()
+        && args.sqlite.is_none()
+        && args.post_command.is_none()
+}
+
+/// Formats the list or applies the record-level output options and `--distinct`.
+/// With `--merge-exceptions` and `--histogram` the exceptions and the event volume are also printed to stderr.
+fn format_output(command: &Commands, text: &str, args: &ParsedArgs) -> String {
+    match command {
+        Commands::List => {
+            let now = args.humanize_dates.then(Utc::now);
+            output::format_list(text, args.list_format, now)
+        }
+        Commands::Retrieve if args.byte_count_only => text.to_string(),
+        Commands::Completions { .. } => text.to_string(),
+        Commands::Count => {
+            let text = filter_outcomes(text, args);
+            output::count_records(&output::process(&text, args)).to_string()
+        }
+        Commands::Retrieve | Commands::Recent { .. } | Commands::Validate => {
+            let text = &*filter_outcomes(text, args);
+            if args.merge_exceptions {
+                let compact = args.compact_errors;
+                if let Err(e) = output::write_exceptions(text, &mut std::io::stderr(), compact) {
+                    eprintln!("Failed to print exceptions: {}", e);
+                }
+            }
+            if let Some(granularity) = args.histogram {
+                let counts = output::histogram(text, granularity, output::timestamp_field(args));
+                eprintln!("{}", output::render_histogram(&counts));
+            }
+            let text = output::process(text, args);
+            if let Some(seconds) = args.detect_gaps {
+                let threshold = chrono::Duration::seconds(seconds as i64);
+                for (before, after) in
+                    output::detect_gaps(&text, output::timestamp_field(args), threshold)
+                {
+                    eprintln!("{}", output::gap_warning(before, after));
+                }
+            }
+            match &args.distinct {
+                Some(path) => output::distinct(&text, path),
+                None => text,
+            }
+        }
+    }
+}
+
+/// Whether the outcomes of the retrieved records are tallied for the summary printed in verbose mode
+fn summarizes_outcomes(command: &Commands, args: &ParsedArgs) -> bool {
+    args.verbose && *command == Commands::Retrieve && !args.byte_count_only
+}
+
+/// Applies `--filter-outcome`, noting the lines dropped for not being JSON in verbose mode
+fn filter_outcomes<'a>(text: &'a str, args: &ParsedArgs) -> Cow<'a, str> {
+    if args.filter_outcomes.is_empty() {
+        return Cow::Borrowed(text);
+    }
+    let (text, skipped) = output::filter_outcomes(text, &args.filter_outcomes);
+    if args.verbose && skipped > 0 {
+        println!("Dropped {} line(s) that are not JSON", skipped);
+    }
+    Cow::Owned(text)
+}
+
+/// Combines the outputs of several `--account-id`s: records are labeled with their account,
+/// lists are printed under a `# <ACCOUNT_ID>` header each, or merged with `--keys-only`
+fn format_accounts(command: &Commands, outputs: &[(String, String)], args: &ParsedArgs) -> String {
+    match command {
+        Commands::List if args.keys_only => outputs
+            .iter()
+            .map(|(_, text)| format_output(command, text, args))
+            .filter(|keys| !keys.is_empty())
+            .collect::<Vec<_>>()
+            .join("\n"),
+        Commands::List => outputs
+            .iter()
+            .map(|(account_id, text)| {
+                format!("# {}\n{}", account_id, format_output(command, text, args))
+            })
+            .collect::<Vec<_>>()
+            .join("\n"),
+        Commands::Retrieve | Commands::Recent { .. } | Commands::Count | Commands::Validate => {
+            let chunks = outputs
+                .iter()
+                .filter(|(_, text)| !text.is_empty())
+                .map(|(account_id, text)| output::label_account(text, account_id))
+                .collect::<Vec<_>>();
+            let text = output::join_chunks(&chunks, args.separator.as_deref());
+            format_output(command, &text, args)
+        }
+        Commands::Completions { .. } => String::new(),
+    }
+}
+
+/// An error returned from `main` is printed as `Error: <message>` and exits with code 1,
+/// the other exit codes of the run are returned as `ExitCode`
 #[tokio::main]
-async fn main() -> Result<(), reqwest::Error> {
-    // the environment configuration
-    let url_env = UrlEnv::get_env();
-    let api_env = ApiEnv::get_env();
+async fn main() -> Result<ExitCode, Error> {
     // the command line arguments
     let args = Args::get_parsed();
+    let error_format = args.error_format;
+    let report_path = args.report.clone();
+    let mut report = Report::new(&args);
+    let started = Instant::now();
+    let result = run(args, &mut report).await;
+    let code = match &result {
+        Ok(code) => *code,
+        Err(e) => {
+            report.add_error(e.to_string());
+            EXIT_ERROR
+        }
+    };
+    // the report is written whether the run succeeded or not
+    if let Some(path) = report_path {
+        if let Err(e) = report.write(&path, code, started.elapsed()) {
+            eprintln!("Failed to write {}: {}", path.display(), e);
+        }
+    }
+    match result {
+        Ok(code) => Ok(u8::try_from(code).map_or(ExitCode::FAILURE, ExitCode::from)),
+        // the standard library prints an error returned from `main` as text only
+        Err(e) if error_format == ErrorFormat::Json => {
+            e.report(error_format, &mut io::stderr());
+            Ok(ExitCode::from(EXIT_ERROR as u8))
+        }
+        Err(e) => Err(e),
+    }
+}
 
+/// Runs the command and returns its exit code, recording what was fetched into the `--report`.
+/// A failure that ends the run is returned for `main` to print.
+async fn run(mut args: ParsedArgs, report: &mut Report) -> Result<i32, Error> {
     // the command to be executed
     // If `args.commands` is `Some`, it returns the cloned value of `args.commands`.
     // Otherwise, it returns the default value `Commands::Retrieve`.
     let command = args.commands.clone().unwrap_or(Commands::Retrieve);
-    // the endpoint for the command
-    let endpoint = command.get_endpoint(&args, &url_env);
-
-    let client = reqwest::Client::new();
-    let text = api::fetch_logs(
-        &client,
-        &endpoint,
-        &api_env.cf_api_key,
-        &api_env.r2_access_key_id,
-        &api_env.r2_secret_access_key,
-    )
-    .await?;
-
-    println!("{}", text);
-
-    Ok(())
+    // completions need neither the flags checked nor a readable config file
+    if let Commands::Completions { shell } = command {
+        // printed with `print!` so the script is captured in tests
+        let mut script = Vec::new();
+        Commands::completions(shell, &mut script);
+        print!("{}", String::from_utf8_lossy(&script));
+        return Ok(0);
+    }
+
+    Args::check(&args)?;
+    let config_file = match (&args.config, paths::config_file()) {
+        (Some(path), _) => ConfigFile::load_required(path)?,
+        (None, Some(path)) => ConfigFile::load(&path)?,
+        (None, None) => ConfigFile::default(),
+    };
+    config_file.check_min_version(env!("CARGO_PKG_VERSION"))?;
+    let credentials = config_file.credentials(args.profile.as_deref())?;
+    rng::init(args.seed);
+
+    // the environment configuration, of each account's profile with `--account-id`
+    if let Some(path) = config::load_env_file(args.env_file.as_deref())? {
+        if args.verbose {
+            println!("Loaded environment variables from {}", path.display());
+        }
+    }
+    credentials.apply_env(args.profile.is_some());
+    // `validate` and `--dry-run` make no network call, so they report the missing account instead
+    let account_id = match args.auto_account
+        && command != Commands::Validate
+        && !args.dry_run
+        && std::env::var("CLOUDFLARE_ACCOUNT_ID").is_err()
+    {
+        true => Some(resolve_account(&args).await?),
+        false => None,
+    };
+    let envs = load_envs(&args, account_id.as_deref())?;
+    report.set_bucket(&envs[0].0.bucket_name);
+    if command == Commands::Validate {
+        let url_envs = envs
+            .into_iter()
+            .map(|(url_env, _)| url_env)
+            .collect::<Vec<_>>();
+        let code = Commands::validate(
+            &args,
+            &url_envs,
+            &mut std::io::stdout(),
+            &mut std::io::stderr(),
+        );
+        return Ok(code);
+    }
+    if args.dry_run {
+        let url_envs = envs
+            .into_iter()
+            .map(|(url_env, _)| url_env)
+            .collect::<Vec<_>>();
+        if let Err(e) = command.dry_run(&args, &url_envs, &mut io::stdout()) {
+            return Ok(report.fail(format!("Failed to write the output: {}", e)));
+        }
+        return Ok(0);
+    }
+
+    if args.from_stdin_keys && command == Commands::Retrieve {
+        args.stdin_objects = match model::read_keys(io::stdin().lock()) {
+            Ok(objects) => objects,
+            Err(e) => {
+                return Ok(report.fail(format!("Failed to read object keys from stdin: {}", e)));
+            }
+        };
+    }
+    let client = api::build_client(&args, &mut std::io::stdout())?;
+    let mut options = FetchOptions::from(&args);
+    if let Some(fd) = args.progress_fd {
+        options.progress = match Progress::from_fd(fd) {
+            Ok(progress) => progress,
+            Err(e) => return Ok(report.fail(e)),
+        };
+    } else if args.verbose {
+        options.progress = Progress::status_line();
+    }
+    let mut out: Box<dyn Write> = match (&args.output, args.rotate_bytes) {
+        (Some(path), Some(limit)) => match create_output(&split::rotated_path(path, 1)) {
+            Ok(file) => Box::new(split::RotatingFile::new(path, limit, file)),
+            Err(e) => return Ok(report.fail(e)),
+        },
+        (Some(path), None) => match create_output(path) {
+            Ok(file) => Box::new(file),
+            Err(e) => return Ok(report.fail(e)),
+        },
+        (None, _) => Box::new(io::stdout()),
+    };
+    if args.line_buffered {
+        out = Box::new(output::LineFlushed(out));
+    }
+    let started = Instant::now();
+    let tally = summarizes_outcomes(&command, &args);
+    let mut outcomes = OutcomeCounts::default();
+    if args.follow && command == Commands::Retrieve {
+        let (url_env, api_env) = &envs[0];
+        let follow = Follow::from(&args);
+        let mut lines = 0;
+        let stats = follow::run(
+            &client,
+            &args,
+            url_env,
+            api_env,
+            &options,
+            &follow,
+            |text| {
+                if tally {
+                    outcomes.merge(OutcomeCounts::from_text(text));
+                }
+                let text = format_output(&command, text, &args);
+                let text = if args.split_by_level {
+                    let (text, errors) = output::split_by_level(&text);
+                    if !errors.is_empty() {
+                        eprintln!("{}", render_records(&errors, &args));
+                    }
+                    text
+                } else {
+                    text
+                };
+                let text = render_records(&text, &args);
+                let text = match &args.separator {
+                    Some(separator) if lines > 0 => format!("{}\n{}", separator, text),
+                    _ => text,
+                };
+                lines += text.lines().count();
+                if let Err(e) = write_output(&mut out, &text, args.framing) {
+                    eprintln!("Failed to write the output: {}", e);
+                }
+            },
+        )
+        .await?;
+        options.progress.finish();
+        if tally && !outcomes.is_empty() {
+            eprintln!("Outcomes: {}", outcomes);
+        }
+        if args.verbose_json {
+            eprintln!("{}", stats.to_json(started.elapsed(), lines));
+        }
+        report.record(&stats, Some(lines));
+        return Ok(0);
+    }
+    let streamed = can_stream(&command, &args);
+    let (text, stats) = if streamed {
+        let (url_env, api_env) = &envs[0];
+        let mut out = output::OutcomeTally::new(&mut out, tally);
+        let stats = if time_chunks(&args.start_time, &args.end_time, args.chunk_minutes).len() > 1 {
+            let endpoints = command.chunk_endpoints(&args, url_env);
+            api::stream_chunks(&client, &endpoints, &args, api_env, &options, &mut out).await?
+        } else {
+            let endpoint = command.get_endpoint(&args, url_env);
+            api::stream_logs(
+                &client,
+                &endpoint,
+                &api_env.cf_api_key,
+                &api_env.r2_access_key_id,
+                &api_env.r2_secret_access_key,
+                &options,
+                &mut out,
+            )
+            .await?
+        };
+        outcomes = out.finish();
+        (String::new(), stats)
+    } else if !labels_accounts(&args) {
+        let (url_env, api_env) = &envs[0];
+        let (text, stats) =
+            api::fetch_command(&client, &command, &args, url_env, api_env, &options).await?;
+        if tally {
+            outcomes = OutcomeCounts::from_text(&text);
+        }
+        (format_output(&command, &text, &args), stats)
+    } else {
+        let (outputs, stats) =
+            api::fetch_accounts(&client, &command, &args, &envs, &options).await?;
+        if tally {
+            outputs
+                .iter()
+                .for_each(|(_, text)| outcomes.merge(OutcomeCounts::from_text(text)));
+        }
+        (format_accounts(&command, &outputs, &args), stats)
+    };
+    report.record(&stats, (!streamed).then(|| text.lines().count()));
+
+    let failure_exit_code = failure_exit_code(&stats, args.partial_ok);
+    if !stats.failed.is_empty() {
+        eprintln!(
+            "Failed to fetch {} object(s) or window(s):",
+            stats.failed.len()
+        );
+        stats.failed.iter().for_each(|key| eprintln!("  {}", key));
+        if !args.partial_ok {
+            if !args.quiet {
+                eprintln!(
+                    "Use --partial-ok to print the logs of the objects or windows that succeeded"
+                );
+            }
+            return Ok(EXIT_ERROR);
+        }
+    }
+
+    let is_empty = if streamed {
+        stats.bytes == 0
+    } else {
+        text.trim().is_empty()
+    } && stats.is_success();
+    let empty_exit_code = is_empty.then(|| match args.quiet {
+        // the exit code of an empty range still applies
+        true => args.empty_behavior.report(&mut io::sink()),
+        false => args.empty_behavior.report(&mut io::stderr()),
+    });
+    match (args.split_by, &args.split_dir) {
+        _ if is_empty || streamed => {}
+        (Some(split_by), Some(dir)) if command != Commands::List => {
+            match split::write_records(
+                &text,
+                dir,
+                split_by,
+                output::timestamp_field(&args),
+                args.rotate_bytes,
+            ) {
+                Ok(written) => {
+                    if args.verbose {
+                        written.iter().for_each(|(path, records)| {
+                            println!("Wrote {} record(s) to {}", records, path.display())
+                        });
+                    }
+                }
+                Err(e) => {
+                    let message =
+                        format!("Failed to write split output to {}: {}", dir.display(), e);
+                    return Ok(report.fail(message));
+                }
+            }
+        }
+        _ if command != Commands::List && args.sqlite.is_some() => {
+            let path = args.sqlite.as_deref().expect("sqlite is set");
+            match sqlite::insert_records(path, &text, &args.fields) {
+                Ok(rows) => {
+                    if args.verbose {
+                        println!("Inserted {} record(s) into {}", rows, path.display());
+                    }
+                }
+                Err(e) => {
+                    return Ok(report.fail(format!("Failed to write {}: {}", path.display(), e)));
+                }
+            }
+        }
+        _ => {
+            let text = if command == Commands::List {
+                text.clone()
+            } else if args.split_by_level {
+                let (text, errors) = output::split_by_level(&text);
+                if !errors.is_empty() {
+                    eprintln!("{}", render_records(&errors, &args));
+                }
+                render_records(&text, &args)
+            } else {
+                render_records(&text, &args)
+            };
+            let text = if args.line_numbers {
+                output::number_lines(&text)
+            } else {
+                text
+            };
+            match &args.post_command {
+                Some(post_command) => {
+                    if let Err(e) = pipe::run(post_command, &text) {
+                        return Ok(report.fail(e));
+                    }
+                }
+                None => {
+                    if let Err(e) = write_output(&mut out, &text, args.framing) {
+                        return Ok(report.fail(format!("Failed to write the output: {}", e)));
+                    }
+                }
+            }
+        }
+    }
+
+    if let Some(path) = &args.emit_sources {
+        if let Err(e) = std::fs::write(path, format!("{}\n", stats.sources_json())) {
+            return Ok(report.fail(format!("Failed to write {}: {}", path.display(), e)));
+        }
+    }
+    options.progress.finish();
+    if tally && !outcomes.is_empty() {
+        eprintln!("Outcomes: {}", outcomes);
+    }
+    if let Some(digest) = options.checksum.hex_digest() {
+        eprintln!("sha256: {}", digest);
+        if let Some(path) = args.output.as_ref().filter(|_| args.rotate_bytes.is_none()) {
+            let written = out.flush().and_then(|_| api::Checksum::write_sidecar(path));
+            match written {
+                Ok(sidecar) if args.verbose => println!("Wrote {}", sidecar.display()),
+                Ok(_) => {}
+                Err(e) => return Ok(report.fail(format!("Failed to write the checksum: {}", e))),
+            }
+        }
+    }
+    if args.verbose_json {
+        eprintln!("{}", stats.to_json(started.elapsed(), text.lines().count()));
+    }
+    if let Err(e) = stats.report_timing(args.timing, started.elapsed(), &mut std::io::stderr()) {
+        eprintln!("Failed to print timing: {}", e);
+    }
+    Ok(empty_exit_code
+        .filter(|code| *code != 0)
+        .or(failure_exit_code)
+        .unwrap_or(0))
+}
+
+#[cfg(test)]
+mod main_tests {
+    use super::*;
+    use clap::Parser;
+
+    #[tokio::test]
+    async fn test_completions_ignore_a_broken_config() {
+        let args = Args::parse_from([
+            "r2logs",
+            "--config",
+            "/nonexistent/r2logs/config.toml",
+            "completions",
+            "bash",
+        ])
+        .into_parsed()
+        .unwrap();
+        let mut report = Report::new(&args);
+        assert_eq!(run(args, &mut report).await.unwrap(), 0);
+    }
+
+    #[test]
+    fn test_failure_exit_code() {
+        let ok = FetchStats {
+            status: Some(200),
+            ..Default::default()
+        };
+        assert_eq!(failure_exit_code(&ok, false), None);
+        let rejected = FetchStats {
+            status: Some(403),
+            ..Default::default()
+        };
+        assert_eq!(failure_exit_code(&rejected, true), Some(EXIT_ERROR));
+        let partial = FetchStats {
+            status: Some(403),
+            failed: vec![
+                "window 2/2 from 2024-01-11T16:00:00Z to 2024-01-11T17:00:00Z".to_string(),
+            ],
+            ..Default::default()
+        };
+        assert_eq!(failure_exit_code(&partial, true), Some(EXIT_PARTIAL));
+        assert_eq!(failure_exit_code(&partial, false), Some(EXIT_ERROR));
+    }
+
+    #[test]
+    fn test_single_account_id_is_not_labeled() {
+        let record = "{\"Outcome\":\"ok\"}";
+        let args = Args::parse_from(["r2logs", "--account-id", "a"])
+            .into_parsed()
+            .unwrap();
+        assert!(!labels_accounts(&args));
+        assert!(can_stream(&Commands::Retrieve, &args));
+        assert_eq!(format_output(&Commands::Retrieve, record, &args), record);
+
+        let args = Args::parse_from(["r2logs", "--account-id", "a", "--account-id", "b"])
+            .into_parsed()
+            .unwrap();
+        assert!(labels_accounts(&args));
+        let outputs = [
+            ("a".to_string(), record.to_string()),
+            ("b".to_string(), record.to_string()),
+        ];
+        assert_eq!(
+            format_accounts(&Commands::Retrieve, &outputs, &args),
+            "{\"Outcome\":\"ok\",\"AccountID\":\"a\"}\n{\"Outcome\":\"ok\",\"AccountID\":\"b\"}"
+        );
+    }
 }
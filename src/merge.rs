@@ -0,0 +1,74 @@
+use std::{cmp::Reverse, collections::BinaryHeap};
+
+use chrono::{DateTime, Utc};
+use serde_json::Value;
+
+use crate::output;
+
+/// Orders records by their timestamp, those without one last
+fn sort_key(line: &str, field: &str) -> (bool, Option<DateTime<Utc>>) {
+    let time = serde_json::from_str::<Value>(line)
+        .ok()
+        .and_then(|record| output::event_time(&record, field));
+    (time.is_none(), time)
+}
+
+/// Merges the records of several objects into one stream in timestamp order, for `--chronological`.
+/// Each object is expected to be in timestamp order already, as the Logs Engine writes them,
+/// so only the next record of every object is compared. Ties keep the order of the objects.
+pub fn merge_chronological(chunks: &[String], field: &str) -> String {
+    let mut chunk_lines = chunks
+        .iter()
+        .map(|chunk| chunk.lines().filter(|line| !line.trim().is_empty()))
+        .collect::<Vec<_>>();
+    let mut heads = BinaryHeap::new();
+    for (index, lines) in chunk_lines.iter_mut().enumerate() {
+        if let Some(line) = lines.next() {
+            heads.push(Reverse((sort_key(line, field), index, line)));
+        }
+    }
+    let mut merged = Vec::new();
+    while let Some(Reverse((_, index, line))) = heads.pop() {
+        merged.push(line);
+        if let Some(next) = chunk_lines[index].next() {
+            heads.push(Reverse((sort_key(next, field), index, next)));
+        }
+    }
+    merged.join("\n")
+}
+
+#[cfg(test)]
+mod merge_tests {
+    use super::*;
+
+    #[test]
+    fn test_merge_chronological() {
+        let chunks = [
+            [
+                r#"{"EventTimestampMs":1,"object":"a"}"#,
+                r#"{"EventTimestampMs":4,"object":"a"}"#,
+                r#"{"EventTimestampMs":5,"object":"a"}"#,
+            ]
+            .join("\n"),
+            [
+                r#"{"EventTimestampMs":2,"object":"b"}"#,
+                r#"{"EventTimestampMs":4,"object":"b"}"#,
+                r#"{"object":"b"}"#,
+            ]
+            .join("\n"),
+            String::new(),
+        ];
+        let merged = merge_chronological(&chunks, "EventTimestampMs");
+        assert_eq!(
+            merged.lines().collect::<Vec<_>>(),
+            [
+                r#"{"EventTimestampMs":1,"object":"a"}"#,
+                r#"{"EventTimestampMs":2,"object":"b"}"#,
+                r#"{"EventTimestampMs":4,"object":"a"}"#,
+                r#"{"EventTimestampMs":4,"object":"b"}"#,
+                r#"{"EventTimestampMs":5,"object":"a"}"#,
+                r#"{"object":"b"}"#,
+            ]
+        );
+    }
+}
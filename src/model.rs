@@ -0,0 +1,331 @@
+use chrono::{DateTime, FixedOffset};
+use serde::{Deserialize, Serialize};
+
+/// ## R2 Object
+/// An object returned by the `list` endpoint.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Default)]
+pub struct R2Object {
+    pub key: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub size: Option<u64>,
+    #[serde(
+        default,
+        alias = "lastModified",
+        alias = "uploaded",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub last_modified: Option<String>,
+}
+
+/// A list entry, either a bare object key or an object with metadata
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum ListEntry {
+    Key(String),
+    Object(R2Object),
+}
+
+impl From<ListEntry> for R2Object {
+    fn from(entry: ListEntry) -> Self {
+        match entry {
+            ListEntry::Key(key) => Self {
+                key,
+                ..Default::default()
+            },
+            ListEntry::Object(object) => object,
+        }
+    }
+}
+
+/// The `list` response, either a bare array or wrapped in the Cloudflare API envelope
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum ListResponse {
+    Bare(Vec<ListEntry>),
+    Envelope {
+        result: Vec<ListEntry>,
+        #[serde(default)]
+        result_info: Option<ResultInfo>,
+    },
+}
+
+/// Pagination of an enveloped `list` response
+#[derive(Deserialize)]
+struct ResultInfo {
+    #[serde(default)]
+    cursor: Option<String>,
+    #[serde(default)]
+    is_truncated: Option<bool>,
+}
+
+/// ## List Page
+/// One page of the `list` response and the cursor of the next page, `None` on the last page.
+pub struct ListPage {
+    pub objects: Vec<R2Object>,
+    pub cursor: Option<String>,
+}
+
+/// Parses one page of the body returned by the `list` endpoint
+pub fn parse_list_page(text: &str) -> Result<ListPage, serde_json::Error> {
+    let (entries, result_info) = match serde_json::from_str::<ListResponse>(text)? {
+        ListResponse::Bare(entries) => (entries, None),
+        ListResponse::Envelope {
+            result,
+            result_info,
+        } => (result, result_info),
+    };
+    let cursor = result_info
+        .filter(|info| info.is_truncated != Some(false))
+        .and_then(|info| info.cursor)
+        .filter(|cursor| !cursor.is_empty());
+    Ok(ListPage {
+        objects: entries.into_iter().map(R2Object::from).collect(),
+        cursor,
+    })
+}
+
+/// Parses the body returned by the `list` endpoint into R2 objects
+pub fn parse_list(text: &str) -> Result<Vec<R2Object>, serde_json::Error> {
+    parse_list_page(text).map(|page| page.objects)
+}
+
+/// The errors of a Cloudflare API envelope, `{"success":false,"errors":[{"code":...,"message":...}]}`
+#[derive(Deserialize)]
+struct ErrorEnvelope {
+    #[serde(default)]
+    errors: Vec<ApiError>,
+}
+
+#[derive(Deserialize)]
+struct ApiError {
+    #[serde(default)]
+    code: Option<u32>,
+    #[serde(default)]
+    message: String,
+}
+
+/// R2 error code of a bucket that does not exist
+const NO_SUCH_BUCKET_CODE: u32 = 10006;
+
+/// Whether an error body reports that the bucket does not exist, by its envelope code or message
+pub fn is_bucket_not_found(text: &str) -> bool {
+    serde_json::from_str::<ErrorEnvelope>(text).is_ok_and(|envelope| {
+        envelope.errors.iter().any(|error| {
+            let message = error.message.to_lowercase();
+            error.code == Some(NO_SUCH_BUCKET_CODE)
+                || message.contains("bucket does not exist")
+                || message.contains("nosuchbucket")
+        })
+    })
+}
+
+/// ## Account
+/// An account returned by the accounts endpoint of the Cloudflare API.
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+pub struct Account {
+    pub id: String,
+    #[serde(default)]
+    pub name: String,
+}
+
+#[derive(Deserialize)]
+struct AccountsResponse {
+    result: Vec<Account>,
+}
+
+/// Parses the body returned by the accounts endpoint
+pub fn parse_accounts(text: &str) -> Result<Vec<Account>, serde_json::Error> {
+    serde_json::from_str::<AccountsResponse>(text).map(|response| response.result)
+}
+
+fn last_modified(object: &R2Object) -> Option<DateTime<FixedOffset>> {
+    object
+        .last_modified
+        .as_deref()
+        .and_then(|date| DateTime::parse_from_rfc3339(date).ok())
+}
+
+/// The last modified date of the most recently modified object
+pub fn latest_modified(objects: &[R2Object]) -> Option<DateTime<FixedOffset>> {
+    objects.iter().filter_map(last_modified).max()
+}
+
+/// The `count` most recently modified objects, oldest first.
+/// Objects without a last modified date are considered the oldest.
+pub fn most_recent(mut objects: Vec<R2Object>, count: usize) -> Vec<R2Object> {
+    objects.sort_by_key(last_modified);
+    let skip = objects.len().saturating_sub(count);
+    objects.into_iter().skip(skip).collect()
+}
+
+/// Objects named by keys read one per line, such as the output of `list --keys-only`.
+/// Blank lines are skipped.
+pub fn read_keys(input: impl std::io::BufRead) -> std::io::Result<Vec<R2Object>> {
+    input
+        .lines()
+        .filter(|line| !matches!(line, Ok(line) if line.trim().is_empty()))
+        .map(|line| {
+            line.map(|key| R2Object {
+                key: key.trim().to_string(),
+                ..Default::default()
+            })
+        })
+        .collect()
+}
+
+/// The `Outcome` of a trace event, read by `--filter-outcome` without the rest of the record
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+pub struct EventOutcome {
+    #[serde(default, rename = "Outcome")]
+    pub outcome: Option<String>,
+}
+
+/// ## Trace Event
+/// A Workers trace event, one record of the `retrieve` output.
+/// Only the fields rendered by `--format table` are required; the others default when missing.
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "PascalCase")]
+pub struct TraceEvent {
+    pub event_timestamp_ms: i64,
+    pub outcome: String,
+    #[serde(default)]
+    pub script_name: Option<String>,
+    #[serde(default)]
+    pub event_type: Option<String>,
+    #[serde(default)]
+    pub event: Option<TraceEventInfo>,
+    #[serde(default)]
+    pub logs: Vec<TraceLog>,
+    #[serde(default)]
+    pub exceptions: Vec<TraceException>,
+}
+
+/// The `Event` of a trace event, holding the request of `fetch` events
+#[derive(Deserialize, Debug, Clone, PartialEq, Default)]
+#[serde(rename_all = "PascalCase")]
+pub struct TraceEventInfo {
+    #[serde(default, rename = "RayID")]
+    pub ray_id: Option<String>,
+    #[serde(default)]
+    pub request: Option<TraceRequest>,
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq, Default)]
+#[serde(rename_all = "PascalCase")]
+pub struct TraceRequest {
+    #[serde(default, rename = "URL")]
+    pub url: Option<String>,
+    #[serde(default)]
+    pub method: Option<String>,
+}
+
+/// A `console` call of the script
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "PascalCase")]
+pub struct TraceLog {
+    pub level: String,
+    #[serde(default)]
+    pub message: Vec<serde_json::Value>,
+    #[serde(default)]
+    pub timestamp_ms: Option<i64>,
+}
+
+/// An uncaught exception of the script
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "PascalCase")]
+pub struct TraceException {
+    #[serde(default)]
+    pub name: Option<String>,
+    #[serde(default)]
+    pub message: Option<String>,
+    #[serde(default)]
+    pub timestamp_ms: Option<i64>,
+}
+
+#[cfg(test)]
+mod model_tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_list_page_cursor() {
+        let page = parse_list_page(
+            r#"{"result":["a"],"result_info":{"cursor":"next","is_truncated":true}}"#,
+        )
+        .unwrap();
+        assert_eq!(page.objects[0].key, "a");
+        assert_eq!(page.cursor.as_deref(), Some("next"));
+        for last in [
+            r#"{"result":["a"],"result_info":{"cursor":"next","is_truncated":false}}"#,
+            r#"{"result":["a"],"result_info":{"cursor":""}}"#,
+            r#"{"result":["a"],"result_info":null}"#,
+            r#"["a"]"#,
+        ] {
+            assert_eq!(parse_list_page(last).unwrap().cursor, None);
+        }
+    }
+
+    #[test]
+    fn test_is_bucket_not_found() {
+        assert!(is_bucket_not_found(
+            r#"{"success":false,"errors":[{"code":10006,"message":"The specified bucket does not exist."}]}"#
+        ));
+        assert!(is_bucket_not_found(
+            r#"{"errors":[{"message":"NoSuchBucket: missing"}]}"#
+        ));
+        assert!(!is_bucket_not_found(
+            r#"{"success":false,"errors":[{"code":10000,"message":"Authentication error"}]}"#
+        ));
+        assert!(!is_bucket_not_found("Internal Server Error"));
+    }
+
+    #[test]
+    fn test_parse_list() {
+        let text =
+            r#"[{"key":"20240111/a.log.gz","size":1024,"last_modified":"2024-01-11T15:01:00Z"}]"#;
+        let objects = parse_list(text).unwrap();
+        assert_eq!(
+            objects,
+            vec![R2Object {
+                key: "20240111/a.log.gz".to_string(),
+                size: Some(1024),
+                last_modified: Some("2024-01-11T15:01:00Z".to_string()),
+            }]
+        );
+    }
+
+    #[test]
+    fn test_parse_list_envelope_and_bare_keys() {
+        let text =
+            r#"{"success":true,"errors":[],"result":["20240111/a.log.gz","20240111/b.log.gz"]}"#;
+        let objects = parse_list(text).unwrap();
+        assert_eq!(objects.len(), 2);
+        assert_eq!(objects[0].key, "20240111/a.log.gz");
+        assert_eq!(objects[1].key, "20240111/b.log.gz");
+        assert_eq!(objects[1].size, None);
+    }
+
+    #[test]
+    fn test_parse_list_unexpected_shape() {
+        assert!(parse_list(r#"{"unexpected":true}"#).is_err());
+    }
+
+    #[test]
+    fn test_most_recent() {
+        let object = |key: &str, last_modified: Option<&str>| R2Object {
+            key: key.to_string(),
+            last_modified: last_modified.map(String::from),
+            ..Default::default()
+        };
+        let objects = vec![
+            object("b", Some("2024-01-11T15:02:00Z")),
+            object("none", None),
+            object("c", Some("2024-01-11T15:03:00+00:00")),
+            object("a", Some("2024-01-11T15:01:00Z")),
+        ];
+        let keys = most_recent(objects, 2)
+            .into_iter()
+            .map(|object| object.key)
+            .collect::<Vec<_>>();
+        assert_eq!(keys, vec!["b", "c"]);
+    }
+}
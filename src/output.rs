@@ -0,0 +1,1576 @@
+use chrono::{DateTime, SecondsFormat, Utc};
+use serde_json::{json, Map, Value};
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt,
+    io::{self, Write},
+};
+
+use crate::commands::{Granularity, ListFormat};
+use crate::fields;
+use crate::model::{self, EventOutcome, R2Object, TraceEvent};
+use crate::ParsedArgs;
+
+/// Applies the record-level output options to the newline-delimited JSON returned by the Logs Engine.
+/// Lines that are not valid JSON are passed through, only applying the options that work on raw text.
+pub fn process(text: &str, args: &ParsedArgs) -> String {
+    if is_passthrough(args) {
+        return text.to_string();
+    }
+    let mut lines = text
+        .lines()
+        .flat_map(|line| process_line(line, args))
+        .collect::<Vec<_>>();
+    if args.sort {
+        sort_by_time(&mut lines, timestamp_field(args));
+    }
+    lines.join("\n")
+}
+
+/// Whether no record-level option is set, so the records are output unchanged
+pub fn is_passthrough(args: &ParsedArgs) -> bool {
+    !args.flatten_logs
+        && !args.strip_ansi
+        && !args.sanitize
+        && args.set_fields.is_empty()
+        && args.add_buckets.is_empty()
+        && !args.canonical
+        && args.field_order.is_empty()
+        && args.fields.is_empty()
+        && args.drop_fields.is_empty()
+        && !args.sort
+        && !args.redact_urls
+        && args.min_timestamp.is_none()
+        && args.max_timestamp.is_none()
+}
+
+/// Timestamp field used when `--timestamp-field` is not given
+pub const DEFAULT_TIMESTAMP_FIELD: &str = "EventTimestampMs";
+
+/// The dotted path of the records' timestamp, `--timestamp-field` or `EventTimestampMs`
+pub fn timestamp_field(args: &ParsedArgs) -> &str {
+    args.timestamp_field
+        .as_deref()
+        .unwrap_or(DEFAULT_TIMESTAMP_FIELD)
+}
+
+/// Sorts the lines oldest first by their timestamp field.
+/// The sort is stable and lines without a timestamp go last.
+fn sort_by_time(lines: &mut [String], field: &str) {
+    lines.sort_by_cached_key(|line| {
+        let time = serde_json::from_str::<Value>(line)
+            .ok()
+            .and_then(|record| event_time(&record, field));
+        (time.is_none(), time)
+    });
+}
+
+fn process_line(line: &str, args: &ParsedArgs) -> Vec<String> {
+    let Ok(record) = serde_json::from_str::<Value>(line) else {
+        let mut line = line.to_string();
+        if args.strip_ansi {
+            line = strip_ansi(&line);
+        }
+        if args.sanitize {
+            line = sanitize(&line);
+        }
+        return vec![line];
+    };
+    if !within_bounds(&record, args) {
+        return Vec::new();
+    }
+    let mut record = record;
+    if args.redact_urls {
+        redact_url(&mut record);
+    }
+    let mut records = if args.flatten_logs {
+        flatten_logs(&record)
+    } else {
+        vec![record]
+    };
+    for record in &mut records {
+        if args.strip_ansi {
+            map_strings(record, &strip_ansi);
+        }
+        if args.sanitize {
+            map_strings(record, &sanitize);
+        }
+        for (path, value) in &args.set_fields {
+            fields::set(record, path, Value::String(value.clone()));
+        }
+        for (field, granularity) in &args.add_buckets {
+            if let Some(time) = event_time(record, timestamp_field(args)) {
+                let bucket = granularity
+                    .truncate(time)
+                    .to_rfc3339_opts(SecondsFormat::Secs, true);
+                fields::set(record, field, Value::String(bucket));
+            }
+        }
+    }
+    if !args.fields.is_empty() {
+        records = records
+            .iter()
+            .map(|record| fields::project(record, &args.fields))
+            .collect();
+    }
+    for record in &mut records {
+        for path in &args.drop_fields {
+            fields::remove(record, path);
+        }
+    }
+    if args.canonical {
+        records = records.into_iter().map(canonicalize).collect();
+    }
+    if !args.field_order.is_empty() {
+        records = records
+            .into_iter()
+            .map(|record| order_fields(record, &args.field_order))
+            .collect();
+    }
+    records.iter().map(Value::to_string).collect()
+}
+
+/// The lines whose `Outcome` is one of `outcomes`, unchanged, and the number of lines dropped for not being JSON
+pub fn filter_outcomes(text: &str, outcomes: &[String]) -> (String, usize) {
+    let mut skipped = 0;
+    let lines = text
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .filter(|line| match serde_json::from_str::<EventOutcome>(line) {
+            Ok(event) => event
+                .outcome
+                .is_some_and(|outcome| outcomes.contains(&outcome)),
+            Err(_) => {
+                skipped += 1;
+                false
+            }
+        })
+        .collect::<Vec<_>>();
+    (lines.join("\n"), skipped)
+}
+
+/// Whether the record's timestamp lies within `--min-timestamp` and `--max-timestamp`, both inclusive.
+/// Records without a readable timestamp are kept.
+fn within_bounds(record: &Value, args: &ParsedArgs) -> bool {
+    if args.min_timestamp.is_none() && args.max_timestamp.is_none() {
+        return true;
+    }
+    let Some(time) = event_time(record, timestamp_field(args)) else {
+        return true;
+    };
+    args.min_timestamp.is_none_or(|min| time >= min)
+        && args.max_timestamp.is_none_or(|max| time <= max)
+}
+
+/// Dotted path of the request URL of a trace event
+const REQUEST_URL_FIELD: &str = "Event.Request.URL";
+
+/// Strips the query string and fragment of the request URL, which often carry tokens or personal data
+fn redact_url(record: &mut Value) {
+    let Some(url) = fields::get(record, REQUEST_URL_FIELD).and_then(Value::as_str) else {
+        return;
+    };
+    if let Some(end) = url.find(['?', '#']) {
+        let redacted = url[..end].to_string();
+        fields::set(record, REQUEST_URL_FIELD, Value::String(redacted));
+    }
+}
+
+/// Rebuilds every object with its keys sorted lexicographically, recursively,
+/// so equal records serialize to identical text regardless of the order they arrived in
+fn canonicalize(value: Value) -> Value {
+    match value {
+        Value::Object(map) => {
+            let mut entries = map.into_iter().collect::<Vec<_>>();
+            entries.sort_by(|(a, _), (b, _)| a.cmp(b));
+            Value::Object(
+                entries
+                    .into_iter()
+                    .map(|(key, value)| (key, canonicalize(value)))
+                    .collect(),
+            )
+        }
+        Value::Array(values) => Value::Array(values.into_iter().map(canonicalize).collect()),
+        value => value,
+    }
+}
+
+/// The unique values of the dotted `path` across the records, one per line for `--distinct`.
+/// Numbers come first in numeric order, then the other values sorted as text; strings are printed unquoted.
+/// Records without the field and lines that are not valid JSON are ignored.
+pub fn distinct(text: &str, path: &str) -> String {
+    let mut values = text
+        .lines()
+        .filter_map(|line| serde_json::from_str::<Value>(line).ok())
+        .filter_map(|record| fields::get(&record, path).cloned())
+        .collect::<Vec<_>>();
+    let render = |value: &Value| match value {
+        Value::String(s) => s.clone(),
+        other => other.to_string(),
+    };
+    values.sort_by(|a, b| match (a.as_f64(), b.as_f64()) {
+        (Some(a), Some(b)) => a.total_cmp(&b),
+        (Some(_), None) => std::cmp::Ordering::Less,
+        (None, Some(_)) => std::cmp::Ordering::Greater,
+        (None, None) => render(a).cmp(&render(b)),
+    });
+    values.dedup();
+    values.iter().map(render).collect::<Vec<_>>().join("\n")
+}
+
+/// Widest bar of a `--histogram`, the busiest bucket
+const HISTOGRAM_WIDTH: usize = 50;
+
+/// Counts the records per minute, hour or day of their timestamp at the dotted `field`,
+/// oldest bucket first. Records without a readable timestamp are not counted.
+pub fn histogram(text: &str, granularity: Granularity, field: &str) -> Vec<(DateTime<Utc>, usize)> {
+    let mut counts = std::collections::BTreeMap::new();
+    for time in text
+        .lines()
+        .filter_map(|line| serde_json::from_str::<Value>(line).ok())
+        .filter_map(|record| event_time(&record, field))
+    {
+        *counts.entry(granularity.truncate(time)).or_insert(0) += 1;
+    }
+    counts.into_iter().collect()
+}
+
+/// The gaps longer than `threshold` between the timestamps of consecutive records, in output order,
+/// as the times before and after each gap. Records without a readable timestamp are skipped.
+pub fn detect_gaps(
+    text: &str,
+    field: &str,
+    threshold: chrono::Duration,
+) -> Vec<(DateTime<Utc>, DateTime<Utc>)> {
+    let times = text
+        .lines()
+        .filter_map(|line| serde_json::from_str::<Value>(line).ok())
+        .filter_map(|record| event_time(&record, field))
+        .collect::<Vec<_>>();
+    times
+        .windows(2)
+        .filter(|pair| pair[1] - pair[0] > threshold)
+        .map(|pair| (pair[0], pair[1]))
+        .collect()
+}
+
+/// The warning printed by `--detect-gaps` for a gap
+pub fn gap_warning(before: DateTime<Utc>, after: DateTime<Utc>) -> String {
+    format!(
+        "Warning: gap of {}s between {} and {}, logs may be missing",
+        (after - before).num_milliseconds() as f64 / 1000.0,
+        before.to_rfc3339_opts(SecondsFormat::Millis, true),
+        after.to_rfc3339_opts(SecondsFormat::Millis, true)
+    )
+}
+
+/// Renders the histogram as one line per bucket: its start time, a bar scaled to the busiest bucket and the count
+pub fn render_histogram(counts: &[(DateTime<Utc>, usize)]) -> String {
+    let max = counts.iter().map(|(_, count)| *count).max().unwrap_or(0);
+    counts
+        .iter()
+        .map(|(time, count)| {
+            // every nonempty bucket gets at least one mark
+            let width = (count * HISTOGRAM_WIDTH).div_ceil(max);
+            format!(
+                "{}  {:<HISTOGRAM_WIDTH$}  {}",
+                time.to_rfc3339_opts(SecondsFormat::Secs, true),
+                "#".repeat(width),
+                count
+            )
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// The number of newline-delimited records, for the `count` command; blank lines are not counted
+pub fn count_records(text: &str) -> usize {
+    text.lines().filter(|line| !line.trim().is_empty()).count()
+}
+
+/// ## Line Flushed
+/// A writer flushing the inner writer after each line, for `--line-buffered`.
+/// Each write stops at the end of the first line so `write_all` flushes every line of a chunk.
+pub struct LineFlushed<W>(pub W);
+
+impl<W: Write> Write for LineFlushed<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let end = buf
+            .iter()
+            .position(|byte| *byte == b'\n')
+            .map_or(buf.len(), |index| index + 1);
+        let written = self.0.write(&buf[..end])?;
+        if written > 0 && buf[written - 1] == b'\n' {
+            self.0.flush()?;
+        }
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.0.flush()
+    }
+}
+
+/// ## Outcome Counts
+/// The number of records per `Outcome`, printed at the end in verbose mode.
+/// Records that are not trace events, or have no outcome, are counted as `unknown`.
+#[derive(Debug, Default, PartialEq)]
+pub struct OutcomeCounts(BTreeMap<String, usize>);
+
+impl OutcomeCounts {
+    pub fn from_text(text: &str) -> Self {
+        let mut counts = Self::default();
+        text.lines().for_each(|line| counts.add(line));
+        counts
+    }
+
+    /// Counts a line of the output, skipping blank ones
+    pub fn add(&mut self, line: &str) {
+        if line.trim().is_empty() {
+            return;
+        }
+        let outcome = serde_json::from_str::<EventOutcome>(line)
+            .ok()
+            .and_then(|event| event.outcome)
+            .unwrap_or_else(|| "unknown".to_string());
+        *self.0.entry(outcome).or_insert(0) += 1;
+    }
+
+    pub fn merge(&mut self, other: OutcomeCounts) {
+        for (outcome, count) in other.0 {
+            *self.0.entry(outcome).or_insert(0) += count;
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+/// The most frequent outcome first, e.g. `ok: 412, exception: 7, canceled: 2`
+impl fmt::Display for OutcomeCounts {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut counts = self.0.iter().collect::<Vec<_>>();
+        counts.sort_by(|a, b| b.1.cmp(a.1).then(a.0.cmp(b.0)));
+        let counts = counts
+            .iter()
+            .map(|(outcome, count)| format!("{}: {}", outcome, count))
+            .collect::<Vec<_>>();
+        write!(f, "{}", counts.join(", "))
+    }
+}
+
+/// ## Outcome Tally
+/// A writer counting the outcomes of the lines written through it, for the summary of streamed logs.
+/// Counts nothing when disabled.
+pub struct OutcomeTally<W> {
+    inner: W,
+    counts: Option<OutcomeCounts>,
+    partial: Vec<u8>,
+}
+
+impl<W: Write> OutcomeTally<W> {
+    pub fn new(inner: W, enabled: bool) -> Self {
+        Self {
+            inner,
+            counts: enabled.then(OutcomeCounts::default),
+            partial: Vec::new(),
+        }
+    }
+
+    /// The counts, including a last line without a newline
+    pub fn finish(mut self) -> OutcomeCounts {
+        let mut counts = self.counts.take().unwrap_or_default();
+        counts.add(&String::from_utf8_lossy(&self.partial));
+        counts
+    }
+}
+
+impl<W: Write> Write for OutcomeTally<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        if let Some(counts) = &mut self.counts {
+            self.partial.extend_from_slice(&buf[..written]);
+            while let Some(end) = self.partial.iter().position(|byte| *byte == b'\n') {
+                let line = self.partial.drain(..=end).collect::<Vec<_>>();
+                counts.add(&String::from_utf8_lossy(&line));
+            }
+        }
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// ## Boundary Records
+/// The records of a `--chunk-minutes` window that repeat the previous window, whose boundary is inclusive.
+/// Only the records timestamped within the second of the shared boundary are compared, each dropped
+/// as many times as the previous window returned it, so identical records elsewhere are all kept.
+#[derive(Default)]
+pub struct BoundaryRecords {
+    field: String,
+    start: Option<DateTime<Utc>>,
+    end: Option<DateTime<Utc>>,
+    previous: HashMap<String, usize>,
+    current: HashMap<String, usize>,
+    /// Records dropped from the current window
+    pub repeated: usize,
+}
+
+impl BoundaryRecords {
+    pub fn new(field: &str) -> Self {
+        Self {
+            field: field.to_string(),
+            ..Default::default()
+        }
+    }
+
+    /// Starts the window from `start` to `end`, following the window passed before
+    pub fn next_window(&mut self, start: &str, end: &str) {
+        let parse = |time: &str| {
+            DateTime::parse_from_rfc3339(time)
+                .ok()
+                .map(|time| time.with_timezone(&Utc))
+        };
+        self.previous = std::mem::take(&mut self.current);
+        self.start = parse(start);
+        self.end = parse(end);
+        self.repeated = 0;
+    }
+
+    /// Whether a line of the current window is kept, i.e. it is not a record of the boundary already returned
+    pub fn keep(&mut self, line: &str) -> bool {
+        let Some(time) = serde_json::from_str::<Value>(line)
+            .ok()
+            .and_then(|record| event_time(&record, &self.field))
+        else {
+            return true;
+        };
+        let at = |boundary: Option<DateTime<Utc>>| {
+            boundary.is_some_and(|boundary| {
+                boundary <= time && time < boundary + chrono::Duration::seconds(1)
+            })
+        };
+        if at(self.start) {
+            if let Some(count) = self.previous.get_mut(line).filter(|count| **count > 0) {
+                *count -= 1;
+                self.repeated += 1;
+                return false;
+            }
+        }
+        if at(self.end) {
+            *self.current.entry(line.to_string()).or_default() += 1;
+        }
+        true
+    }
+}
+
+/// ## Boundary Dedup
+/// A writer dropping the records that repeat the previous window, see [`BoundaryRecords`], for windows
+/// streamed one after the other. Lines are written whole; [`BoundaryDedup::next_window`] starts each window.
+pub struct BoundaryDedup<W> {
+    inner: W,
+    records: BoundaryRecords,
+    partial: Vec<u8>,
+    closed: bool,
+}
+
+impl<W: Write> BoundaryDedup<W> {
+    pub fn new(inner: W, field: &str) -> Self {
+        Self {
+            inner,
+            records: BoundaryRecords::new(field),
+            partial: Vec::new(),
+            closed: false,
+        }
+    }
+
+    /// Ends the previous window, whose last line may have no newline, and starts the window from `start` to `end`
+    pub fn next_window(&mut self, start: &str, end: &str) -> io::Result<()> {
+        if !self.partial.is_empty() {
+            self.partial.push(b'\n');
+            self.finish()?;
+        }
+        self.records.next_window(start, end);
+        Ok(())
+    }
+
+    /// Writes the last line of the last window, when it has no newline
+    pub fn finish(&mut self) -> io::Result<()> {
+        let line = std::mem::take(&mut self.partial);
+        self.write_line(&line)?;
+        self.inner.flush()
+    }
+
+    /// Whether writing failed, e.g. a closed stdout, so the remaining windows need not be fetched
+    pub fn is_closed(&self) -> bool {
+        self.closed
+    }
+
+    fn write_line(&mut self, line: &[u8]) -> io::Result<()> {
+        if !self.records.keep(String::from_utf8_lossy(line).trim_end()) {
+            return Ok(());
+        }
+        self.inner
+            .write_all(line)
+            .inspect_err(|_| self.closed = true)
+    }
+}
+
+impl<W: Write> Write for BoundaryDedup<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.partial.extend_from_slice(buf);
+        while let Some(end) = self.partial.iter().position(|byte| *byte == b'\n') {
+            let line = self.partial.drain(..=end).collect::<Vec<_>>();
+            self.write_line(&line)?;
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Joins the outputs of several objects, accounts or polls, with a `--separator` line between two chunks
+pub fn join_chunks(chunks: &[String], separator: Option<&str>) -> String {
+    match separator {
+        Some(separator) => chunks.join(&format!("\n{}\n", separator)),
+        None => chunks.join("\n"),
+    }
+}
+
+/// Prefixes each line with its 1-based index and a tab, for `--line-numbers`
+pub fn number_lines(text: &str) -> String {
+    text.lines()
+        .enumerate()
+        .map(|(index, line)| format!("{}\t{}", index + 1, line))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Pretty-prints each JSON line for `--pretty`.
+/// Lines that are not JSON are kept verbatim, with a warning written to `warnings`.
+pub fn pretty(text: &str, warnings: &mut impl Write) -> String {
+    text.lines()
+        .enumerate()
+        .map(|(index, line)| match serde_json::from_str::<Value>(line) {
+            Ok(record) => {
+                serde_json::to_string_pretty(&record).unwrap_or_else(|_| line.to_string())
+            }
+            Err(e) => {
+                if !line.trim().is_empty() {
+                    let _ = writeln!(
+                        warnings,
+                        "Line {} is not JSON ({}), printed as is",
+                        index + 1,
+                        e
+                    );
+                }
+                line.to_string()
+            }
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Whether the record is error-level for `--split-stdout-stderr-by-level`:
+/// a log entry of level `error`, or a trace event with an exception or an error log
+pub fn is_error_level(record: &Value) -> bool {
+    let is_error = |record: &Value| record.get("Level").and_then(Value::as_str) == Some("error");
+    is_error(record)
+        || record.get("Outcome").and_then(Value::as_str) == Some("exception")
+        || record
+            .get("Exceptions")
+            .and_then(Value::as_array)
+            .is_some_and(|exceptions| !exceptions.is_empty())
+        || record
+            .get("Logs")
+            .and_then(Value::as_array)
+            .is_some_and(|logs| logs.iter().any(is_error))
+}
+
+/// Splits the records into the ones for stdout and the error-level ones for stderr, keeping their order.
+/// Lines that are not JSON go to stdout.
+pub fn split_by_level(text: &str) -> (String, String) {
+    let (errors, others): (Vec<&str>, Vec<&str>) = text.lines().partition(|line| {
+        serde_json::from_str::<Value>(line).is_ok_and(|record| is_error_level(&record))
+    });
+    (others.join("\n"), errors.join("\n"))
+}
+
+/// Renders each Workers trace event on one line for `--format table`:
+/// time, outcome, script name, then the request method and URL.
+/// Lines that are not trace events are kept verbatim, with a warning written to `warnings`.
+pub fn format_table(text: &str, warnings: &mut impl Write) -> String {
+    text.lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .map(
+            |(index, line)| match serde_json::from_str::<TraceEvent>(line) {
+                Ok(event) => table_row(&event),
+                Err(e) => {
+                    let _ = writeln!(
+                        warnings,
+                        "Line {} is not a trace event ({}), printed as is",
+                        index + 1,
+                        e
+                    );
+                    line.to_string()
+                }
+            },
+        )
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+fn table_row(event: &TraceEvent) -> String {
+    let time = DateTime::from_timestamp_millis(event.event_timestamp_ms)
+        .map_or("-".to_string(), |time| {
+            time.to_rfc3339_opts(SecondsFormat::Millis, true)
+        });
+    let request = event.event.as_ref().and_then(|info| info.request.as_ref());
+    let method = request.and_then(|request| request.method.as_deref());
+    let url = request.and_then(|request| request.url.as_deref());
+    format!(
+        "{}  {:<9}  {}  {} {}",
+        time,
+        event.outcome,
+        event.script_name.as_deref().unwrap_or("-"),
+        method.unwrap_or("-"),
+        url.unwrap_or("-")
+    )
+}
+
+/// Writes each non-empty line as a frame of its byte length (4 bytes, big-endian) followed by its bytes,
+/// for `--framing length-prefixed`. Returns the number of frames written.
+pub fn write_length_prefixed(text: &str, out: &mut impl Write) -> io::Result<usize> {
+    let mut frames = 0;
+    for line in text.lines().filter(|line| !line.trim().is_empty()) {
+        let length = u32::try_from(line.len())
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "record too large to frame"))?;
+        out.write_all(&length.to_be_bytes())?;
+        out.write_all(line.as_bytes())?;
+        frames += 1;
+    }
+    Ok(frames)
+}
+
+/// Labels each JSON record with the `--account-id` it was fetched from, in an `AccountID` field.
+/// Lines that are not valid JSON are passed through.
+pub fn label_account(text: &str, account_id: &str) -> String {
+    text.lines()
+        .map(|line| match serde_json::from_str::<Value>(line) {
+            Ok(mut record) => {
+                fields::set(&mut record, "AccountID", json!(account_id));
+                record.to_string()
+            }
+            Err(_) => line.to_string(),
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Writes a highlighted block for each entry of the records' `Exceptions` arrays, for `--merge-exceptions`:
+/// the script, Ray ID and time of the event, then the exception's name, message and stack when present.
+/// With `compact` (`--compact-errors`), consecutive exceptions with the same message and stack are written
+/// once, as the first one's block with `(xN)` after the message.
+/// Returns the number of exceptions found.
+pub fn write_exceptions(text: &str, out: &mut impl Write, compact: bool) -> io::Result<usize> {
+    let blocks = text
+        .lines()
+        .filter_map(|line| serde_json::from_str::<Value>(line).ok())
+        .flat_map(|record| exception_blocks(&record))
+        .collect::<Vec<_>>();
+    let mut index = 0;
+    while index < blocks.len() {
+        let (header, message, stack) = &blocks[index];
+        let repeats = if compact {
+            blocks[index..]
+                .iter()
+                .take_while(|(_, other_message, other_stack)| {
+                    other_message == message && other_stack == stack
+                })
+                .count()
+        } else {
+            1
+        };
+        writeln!(out, "\x1b[31m{}", header)?;
+        match repeats {
+            1 => writeln!(out, "{}", message)?,
+            _ => writeln!(out, "{} (x{})", message, repeats)?,
+        }
+        stack
+            .iter()
+            .try_for_each(|line| writeln!(out, "    {}", line))?;
+        write!(out, "\x1b[0m")?;
+        index += repeats;
+    }
+    Ok(blocks.len())
+}
+
+/// The header, `Name: Message` line and stack lines of each exception of a record
+fn exception_blocks(record: &Value) -> Vec<(String, String, Vec<String>)> {
+    let Some(exceptions) = record["Exceptions"].as_array() else {
+        return Vec::new();
+    };
+    let text_of = |value: &Value| match value {
+        Value::String(s) => s.clone(),
+        Value::Null => "-".to_string(),
+        other => other.to_string(),
+    };
+    exceptions
+        .iter()
+        .map(|exception| {
+            let time = event_time(exception, "TimestampMs")
+                .or_else(|| event_time(record, DEFAULT_TIMESTAMP_FIELD))
+                .map_or("-".to_string(), |time| {
+                    time.to_rfc3339_opts(SecondsFormat::Millis, true)
+                });
+            let header = format!(
+                "=== Exception in {} (RayID {}) at {} ===",
+                text_of(&record["ScriptName"]),
+                text_of(&record["Event"]["RayID"]),
+                time
+            );
+            let message = format!(
+                "{}: {}",
+                text_of(&exception["Name"]),
+                text_of(&exception["Message"])
+            );
+            let stack = exception["Stack"]
+                .as_str()
+                .map(|stack| stack.lines().map(|line| line.trim().to_string()).collect())
+                .unwrap_or_default();
+            (header, message, stack)
+        })
+        .collect()
+}
+
+/// The record's timestamp at the dotted `field` as a UTC time,
+/// either milliseconds since the epoch or an RFC3339 string
+pub fn event_time(record: &Value, field: &str) -> Option<DateTime<Utc>> {
+    match fields::get(record, field)? {
+        Value::Number(ms) => ms.as_i64().and_then(DateTime::from_timestamp_millis),
+        Value::String(time) => DateTime::parse_from_rfc3339(time)
+            .ok()
+            .map(|time| time.with_timezone(&Utc)),
+        _ => None,
+    }
+}
+
+/// Explodes a trace event into one record per `Logs[]` entry,
+/// carrying the parent event's context alongside the entry's level, message and timestamp.
+fn flatten_logs(event: &Value) -> Vec<Value> {
+    let Some(logs) = event["Logs"].as_array() else {
+        return vec![];
+    };
+    logs.iter()
+        .map(|log| {
+            json!({
+                "RayID": event["Event"]["RayID"],
+                "ScriptName": event["ScriptName"],
+                "Level": log["Level"],
+                "Message": log["Message"],
+                "TimestampMs": log["TimestampMs"],
+            })
+        })
+        .collect()
+}
+
+/// Rewrites every string in the record with `f`
+fn map_strings(value: &mut Value, f: &impl Fn(&str) -> String) {
+    match value {
+        Value::String(s) => *s = f(s),
+        Value::Array(values) => values.iter_mut().for_each(|value| map_strings(value, f)),
+        Value::Object(map) => map.values_mut().for_each(|value| map_strings(value, f)),
+        _ => {}
+    }
+}
+
+/// Moves the `--field-order` fields to the front of the record in the given order,
+/// keeping the remaining fields in their current order after them
+fn order_fields(record: Value, order: &[String]) -> Value {
+    let Value::Object(mut map) = record else {
+        return record;
+    };
+    let mut ordered = Map::new();
+    for field in order {
+        if let Some(value) = map.shift_remove(field) {
+            ordered.insert(field.clone(), value);
+        }
+    }
+    ordered.extend(map);
+    Value::Object(ordered)
+}
+
+/// Replaces control characters other than newline and tab with their `\uXXXX` escape,
+/// so NUL or BEL in a message cannot reach the terminal or a downstream parser
+fn sanitize(s: &str) -> String {
+    s.chars()
+        .map(|c| match c {
+            '\n' | '\t' => c.to_string(),
+            c if c.is_control() => format!("\\u{:04x}", c as u32),
+            c => c.to_string(),
+        })
+        .collect()
+}
+
+/// Removes ANSI escape sequences: CSI (`ESC [ ... final byte`), OSC (`ESC ] ... BEL` or `ESC ] ... ESC \`)
+/// and two-character escapes (`ESC x`)
+fn strip_ansi(s: &str) -> String {
+    let mut output = String::with_capacity(s.len());
+    let mut chars = s.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c != '\x1b' {
+            output.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('[') => {
+                for c in chars.by_ref() {
+                    if ('@'..='~').contains(&c) {
+                        break;
+                    }
+                }
+            }
+            Some(']') => {
+                while let Some(c) = chars.next() {
+                    if c == '\x07' {
+                        break;
+                    }
+                    if c == '\x1b' && chars.peek() == Some(&'\\') {
+                        chars.next();
+                        break;
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    output
+}
+
+/// Renders the body returned by the `list` endpoint in the requested format.
+/// With `now` (`--humanize-dates`) the table also shows how long ago each object was modified.
+/// Falls back to the raw body with a warning when the response shape is unexpected.
+pub fn format_list(text: &str, format: ListFormat, now: Option<DateTime<Utc>>) -> String {
+    if text.is_empty() {
+        return String::new();
+    }
+    match model::parse_list(text) {
+        Ok(objects) => render_objects(&objects, format, now),
+        Err(e) => {
+            eprintln!("Unexpected list response ({}), printing raw output", e);
+            text.to_string()
+        }
+    }
+}
+
+fn render_objects(objects: &[R2Object], format: ListFormat, now: Option<DateTime<Utc>>) -> String {
+    match format {
+        ListFormat::Plain => objects
+            .iter()
+            .map(|object| object.key.as_str())
+            .collect::<Vec<_>>()
+            .join("\n"),
+        ListFormat::Json => serde_json::to_string(objects).unwrap_or_default(),
+        ListFormat::Table => {
+            let header = ["KEY", "SIZE", "LAST_MODIFIED"].map(String::from);
+            let rows = objects
+                .iter()
+                .map(|object| {
+                    [
+                        object.key.clone(),
+                        object.size.map_or("-".to_string(), |size| size.to_string()),
+                        last_modified_cell(object, now),
+                    ]
+                })
+                .collect::<Vec<_>>();
+            let rows = [header].into_iter().chain(rows).collect::<Vec<_>>();
+            let key_width = rows.iter().map(|row| row[0].len()).max().unwrap_or(0);
+            let size_width = rows.iter().map(|row| row[1].len()).max().unwrap_or(0);
+            rows.iter()
+                .map(|[key, size, last_modified]| {
+                    format!("{key:<key_width$}  {size:>size_width$}  {last_modified}")
+                })
+                .collect::<Vec<_>>()
+                .join("\n")
+        }
+    }
+}
+
+/// The last modified date of a table row, followed by the relative time when humanized
+fn last_modified_cell(object: &R2Object, now: Option<DateTime<Utc>>) -> String {
+    let Some(last_modified) = &object.last_modified else {
+        return "-".to_string();
+    };
+    let relative = now.zip(DateTime::parse_from_rfc3339(last_modified).ok());
+    match relative {
+        Some((now, time)) => format!(
+            "{} ({})",
+            last_modified,
+            humanize_since(time.with_timezone(&Utc), now)
+        ),
+        None => last_modified.clone(),
+    }
+}
+
+/// How long before `now` the time is, in its largest whole unit, e.g. `2 hours ago` or `just now`
+pub fn humanize_since(time: DateTime<Utc>, now: DateTime<Utc>) -> String {
+    let elapsed = now - time;
+    if elapsed.num_seconds() < 0 {
+        return "in the future".to_string();
+    }
+    let (count, unit) = match elapsed {
+        _ if elapsed.num_days() > 0 => (elapsed.num_days(), "day"),
+        _ if elapsed.num_hours() > 0 => (elapsed.num_hours(), "hour"),
+        _ if elapsed.num_minutes() > 0 => (elapsed.num_minutes(), "minute"),
+        _ if elapsed.num_seconds() > 0 => (elapsed.num_seconds(), "second"),
+        _ => return "just now".to_string(),
+    };
+    let plural = if count == 1 { "" } else { "s" };
+    format!("{} {}{} ago", count, unit, plural)
+}
+
+#[cfg(test)]
+mod output_tests {
+    use super::*;
+
+    const EVENT: &str = r#"{"Event":{"RayID":"8443c2c1ba2c4ef1","Request":{"URL":"https://example.com/","Method":"GET"},"Response":{"Status":200}},"EventTimestampMs":1704985180778,"EventType":"fetch","Exceptions":[],"Logs":[{"Level":"log","Message":["first"],"TimestampMs":1704985180778},{"Level":"error","Message":["second"],"TimestampMs":1704985181064}],"Outcome":"ok","ScriptName":"worker","ScriptTags":[]}"#;
+
+    #[test]
+    fn test_filter_outcomes() {
+        let exception = r#"{"Outcome":"exception", "ScriptName":"worker"}"#;
+        let canceled = r#"{"Outcome":"canceled"}"#;
+        let text = [
+            EVENT,
+            exception,
+            "not json",
+            canceled,
+            r#"{"ScriptName":"worker"}"#,
+        ]
+        .join("\n");
+        let (kept, skipped) = filter_outcomes(&text, &["exception".to_string()]);
+        // the raw line is kept, including its spacing
+        assert_eq!(kept, exception);
+        assert_eq!(skipped, 1);
+        let outcomes = ["exception".to_string(), "canceled".to_string()];
+        let (kept, _) = filter_outcomes(&text, &outcomes);
+        assert_eq!(kept, format!("{}\n{}", exception, canceled));
+    }
+
+    #[test]
+    fn test_process_without_options() {
+        let args = ParsedArgs::default();
+        assert_eq!(process(EVENT, &args), EVENT);
+    }
+
+    #[test]
+    fn test_flatten_logs() {
+        let args = ParsedArgs {
+            flatten_logs: true,
+            ..Default::default()
+        };
+        let output = process(EVENT, &args);
+        let records = output
+            .lines()
+            .map(|line| serde_json::from_str::<Value>(line).unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(records.len(), 2);
+        for record in &records {
+            assert_eq!(record["RayID"], "8443c2c1ba2c4ef1");
+            assert_eq!(record["ScriptName"], "worker");
+        }
+        assert_eq!(records[0]["Level"], "log");
+        assert_eq!(records[0]["Message"], json!(["first"]));
+        assert_eq!(records[0]["TimestampMs"], 1704985180778u64);
+        assert_eq!(records[1]["Level"], "error");
+        assert_eq!(records[1]["Message"], json!(["second"]));
+        assert_eq!(records[1]["TimestampMs"], 1704985181064u64);
+    }
+
+    /// Records the text written before each flush
+    #[derive(Default)]
+    struct FlushRecorder {
+        pending: Vec<u8>,
+        flushed: Vec<String>,
+    }
+
+    impl Write for FlushRecorder {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.pending.extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            let pending = std::mem::take(&mut self.pending);
+            self.flushed.push(String::from_utf8(pending).unwrap());
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_line_flushed() {
+        let mut out = LineFlushed(FlushRecorder::default());
+        out.write_all(b"{\"a\":1}\n{\"b\":2}\n{\"c\"").unwrap();
+        out.write_all(b":3}\n").unwrap();
+        assert_eq!(out.0.flushed, ["{\"a\":1}\n", "{\"b\":2}\n", "{\"c\":3}\n"]);
+        out.write_all(b"partial").unwrap();
+        assert_eq!(out.0.flushed.len(), 3);
+        out.flush().unwrap();
+        assert_eq!(out.0.flushed[3], "partial");
+    }
+
+    #[test]
+    fn test_outcome_counts() {
+        let exception = EVENT.replace(r#""Outcome":"ok""#, r#""Outcome":"exception""#);
+        let body = format!("{}\n{}\n\n{}\nnot json\n{{}}\n", EVENT, exception, EVENT);
+        let counts = OutcomeCounts::from_text(&body);
+        assert_eq!(counts.to_string(), "ok: 2, unknown: 2, exception: 1");
+
+        let mut out = OutcomeTally::new(Vec::new(), true);
+        out.write_all(format!("{}\n{}", EVENT, &exception[..20]).as_bytes())
+            .unwrap();
+        out.write_all(&exception.as_bytes()[20..]).unwrap();
+        assert_eq!(out.finish().to_string(), "exception: 1, ok: 1");
+        let mut out = OutcomeTally::new(Vec::new(), false);
+        out.write_all(format!("{}\n", EVENT).as_bytes()).unwrap();
+        assert!(out.finish().is_empty());
+    }
+
+    #[test]
+    fn test_count_records() {
+        let body = format!("{}\n{}\n\n{}\n", EVENT, EVENT, EVENT);
+        assert_eq!(count_records(&body), 3);
+        assert_eq!(count_records(""), 0);
+        let args = ParsedArgs {
+            flatten_logs: true,
+            ..Default::default()
+        };
+        assert_eq!(count_records(&process(&body, &args)), 6);
+    }
+
+    #[test]
+    fn test_split_by_level() {
+        let args = ParsedArgs {
+            flatten_logs: true,
+            ..Default::default()
+        };
+        let (stdout, stderr) = split_by_level(&process(EVENT, &args));
+        assert!(stdout.contains(r#""Message":["first"]"#));
+        assert!(!stdout.contains("second"));
+        assert!(stderr.contains(r#""Message":["second"]"#));
+        assert!(!stderr.contains("first"));
+
+        // whole trace events go to stderr when they have an error log or an exception
+        let ok = r#"{"Exceptions":[],"Logs":[],"Outcome":"ok"}"#;
+        let exception = r#"{"Exceptions":[{"message":"boom"}],"Logs":[],"Outcome":"exception"}"#;
+        let (stdout, stderr) = split_by_level(&[ok, EVENT, "not json", exception].join("\n"));
+        assert_eq!(stdout, format!("{}\nnot json", ok));
+        assert_eq!(stderr, format!("{}\n{}", EVENT, exception));
+    }
+
+    #[test]
+    fn test_flatten_logs_passes_through_invalid_json() {
+        let args = ParsedArgs {
+            flatten_logs: true,
+            ..Default::default()
+        };
+        assert_eq!(process("not json", &args), "not json");
+    }
+
+    #[test]
+    fn test_strip_ansi() {
+        let args = ParsedArgs {
+            strip_ansi: true,
+            ..Default::default()
+        };
+        let line = r#"{"Logs":[{"Level":"log","Message":["\u001b[31mred\u001b[0m and \u001b[1;32mbold green\u001b[0m"]}]}"#;
+        let output = process(line, &args);
+        let record = serde_json::from_str::<Value>(&output).unwrap();
+        assert_eq!(record["Logs"][0]["Message"][0], "red and bold green");
+        assert_eq!(process("\x1b[33mraw\x1b[0m line", &args), "raw line");
+        assert_eq!(strip_ansi("\x1b]0;title\x07text"), "text");
+        assert_eq!(strip_ansi("\x1b]8;;url\x1b\\link"), "link");
+    }
+
+    #[test]
+    fn test_sanitize() {
+        let args = ParsedArgs {
+            sanitize: true,
+            ..Default::default()
+        };
+        let output = process(r#"{"Message":"a\u0000b\u0007\tc"}"#, &args);
+        let record = serde_json::from_str::<Value>(&output).unwrap();
+        assert_eq!(record["Message"], "a\\u0000b\\u0007\tc");
+        assert_eq!(process("raw\x00line", &args), "raw\\u0000line");
+        // escape sequences are stripped before ESC itself would be escaped
+        assert_eq!(sanitize(&strip_ansi("\x1b[31mred\x1b[0m")), "red");
+        assert_eq!(sanitize("\x1b[31m"), "\\u001b[31m");
+    }
+
+    #[test]
+    fn test_set_fields() {
+        let args = ParsedArgs {
+            set_fields: vec![
+                ("env".to_string(), "prod".to_string()),
+                ("Outcome".to_string(), "tagged".to_string()),
+            ],
+            ..Default::default()
+        };
+        let output = process("{\"Outcome\":\"ok\"}\n{\"Outcome\":\"exception\"}", &args);
+        let records = output
+            .lines()
+            .map(|line| serde_json::from_str::<Value>(line).unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(records.len(), 2);
+        for record in records {
+            assert_eq!(record, json!({"env": "prod", "Outcome": "tagged"}));
+        }
+    }
+
+    #[test]
+    fn test_add_buckets() {
+        let args = ParsedArgs {
+            add_buckets: vec![
+                ("hour".to_string(), Granularity::Hour),
+                ("day".to_string(), Granularity::Day),
+            ],
+            ..Default::default()
+        };
+        // 2024-01-11T14:59:40.778Z
+        let output = process(EVENT, &args);
+        let record = serde_json::from_str::<Value>(&output).unwrap();
+        assert_eq!(record["hour"], "2024-01-11T14:00:00Z");
+        assert_eq!(record["day"], "2024-01-11T00:00:00Z");
+        assert_eq!(
+            process("{\"Outcome\":\"ok\"}", &args),
+            "{\"Outcome\":\"ok\"}"
+        );
+    }
+
+    const LIST: &str = r#"[{"key":"20240111/a.log.gz","size":1024,"last_modified":"2024-01-11T15:01:00Z"},{"key":"20240111/bb.log.gz","size":2,"last_modified":"2024-01-11T15:02:00Z"}]"#;
+
+    #[test]
+    fn test_canonical() {
+        let args = ParsedArgs {
+            canonical: true,
+            ..Default::default()
+        };
+        let first = process(r#"{"b":1,"a":{"d":[{"f":1,"e":2}],"c":3}}"#, &args);
+        let second = process(r#"{"a":{"c":3,"d":[{"e":2,"f":1}]},"b":1}"#, &args);
+        assert_eq!(first, second);
+        assert_eq!(first, r#"{"a":{"c":3,"d":[{"e":2,"f":1}]},"b":1}"#);
+    }
+
+    #[test]
+    fn test_number_lines() {
+        assert_eq!(
+            number_lines("{\"a\":1}\n{\"a\":2}\nraw"),
+            "1\t{\"a\":1}\n2\t{\"a\":2}\n3\traw"
+        );
+        assert_eq!(number_lines(""), "");
+    }
+
+    #[test]
+    fn test_pretty() {
+        let mut warnings = Vec::new();
+        assert_eq!(
+            pretty("{\"a\":1,\"b\":[true]}\nnot json", &mut warnings),
+            "{\n  \"a\": 1,\n  \"b\": [\n    true\n  ]\n}\nnot json"
+        );
+        let warnings = String::from_utf8(warnings).unwrap();
+        assert!(warnings.starts_with("Line 2 is not JSON"));
+    }
+
+    #[test]
+    fn test_format_table() {
+        let scheduled = r#"{"EventTimestampMs":1704985200000,"EventType":"scheduled","Outcome":"exceededCpu","ScriptName":"cron"}"#;
+        let text = [EVENT, scheduled, r#"{"a":1}"#].join("\n");
+        let mut warnings = Vec::new();
+        assert_eq!(
+            format_table(&text, &mut warnings),
+            "2024-01-11T14:59:40.778Z  ok         worker  GET https://example.com/\n\
+             2024-01-11T15:00:00.000Z  exceededCpu  cron  - -\n\
+             {\"a\":1}"
+        );
+        let warnings = String::from_utf8(warnings).unwrap();
+        assert!(warnings.starts_with("Line 3 is not a trace event"));
+    }
+
+    #[test]
+    fn test_detect_gaps() {
+        let text = [
+            1704985180000_i64,
+            1704985185000,
+            1704985305500,
+            1704985306000,
+        ]
+        .map(|ms| format!(r#"{{"EventTimestampMs":{}}}"#, ms))
+        .join("\n");
+        let gaps = detect_gaps(
+            &text,
+            DEFAULT_TIMESTAMP_FIELD,
+            chrono::Duration::seconds(60),
+        );
+        assert_eq!(gaps.len(), 1);
+        assert_eq!(
+            gap_warning(gaps[0].0, gaps[0].1),
+            "Warning: gap of 120.5s between 2024-01-11T14:59:45.000Z and 2024-01-11T15:01:45.500Z, logs may be missing"
+        );
+        assert!(detect_gaps(
+            &text,
+            DEFAULT_TIMESTAMP_FIELD,
+            chrono::Duration::seconds(121)
+        )
+        .is_empty());
+    }
+
+    #[test]
+    fn test_write_length_prefixed() {
+        let text = format!("{}\n\n{{\"n\":2}}\n", EVENT);
+        let mut out = Vec::new();
+        assert_eq!(write_length_prefixed(&text, &mut out).unwrap(), 2);
+
+        let mut records = Vec::new();
+        let mut rest = out.as_slice();
+        while !rest.is_empty() {
+            let (length, tail) = rest.split_at(4);
+            let length = u32::from_be_bytes(length.try_into().unwrap()) as usize;
+            let (record, tail) = tail.split_at(length);
+            records.push(serde_json::from_slice::<Value>(record).unwrap());
+            rest = tail;
+        }
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0]["Event"]["RayID"], "8443c2c1ba2c4ef1");
+        assert_eq!(records[1], json!({"n": 2}));
+    }
+
+    #[test]
+    fn test_label_account() {
+        let text = format!("{}\nnot json", EVENT);
+        let labeled = label_account(&text, "account-a");
+        let mut lines = labeled.lines();
+        let record = serde_json::from_str::<Value>(lines.next().unwrap()).unwrap();
+        assert_eq!(record["AccountID"], "account-a");
+        assert_eq!(record["ScriptName"], "worker");
+        assert_eq!(lines.next(), Some("not json"));
+    }
+
+    #[test]
+    fn test_fields() {
+        let args = ParsedArgs {
+            fields: vec!["Event.RayID".to_string(), "Outcome".to_string()],
+            ..Default::default()
+        };
+        assert_eq!(
+            process(EVENT, &args),
+            r#"{"Event":{"RayID":"8443c2c1ba2c4ef1"},"Outcome":"ok"}"#
+        );
+    }
+
+    #[test]
+    fn test_field_order() {
+        let args = ParsedArgs {
+            field_order: vec![
+                "Outcome".to_string(),
+                "missing".to_string(),
+                "EventType".to_string(),
+            ],
+            ..Default::default()
+        };
+        let record = serde_json::from_str::<Value>(&process(EVENT, &args)).unwrap();
+        let keys = record.as_object().unwrap().keys().collect::<Vec<_>>();
+        assert_eq!(
+            keys,
+            [
+                "Outcome",
+                "EventType",
+                "Event",
+                "EventTimestampMs",
+                "Exceptions",
+                "Logs",
+                "ScriptName",
+                "ScriptTags"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_sort_by_custom_timestamp_field() {
+        let args = ParsedArgs {
+            sort: true,
+            timestamp_field: Some("meta.time".to_string()),
+            ..Default::default()
+        };
+        let text = [
+            r#"{"id":1,"meta":{"time":"2024-01-11T15:02:00Z"}}"#,
+            r#"{"id":2}"#,
+            r#"{"id":3,"meta":{"time":1704985200000}}"#,
+            r#"{"id":4,"meta":{"time":"2024-01-11T15:01:00+00:00"}}"#,
+        ]
+        .join("\n");
+        let ids = process(&text, &args)
+            .lines()
+            .map(|line| {
+                serde_json::from_str::<Value>(line).unwrap()["id"]
+                    .as_i64()
+                    .unwrap()
+            })
+            .collect::<Vec<_>>();
+        // 1704985200000 is 2024-01-11T15:00:00Z
+        assert_eq!(ids, [3, 4, 1, 2]);
+    }
+
+    #[test]
+    fn test_histogram() {
+        // 14:59:40, 14:59:59, 15:00:01 and 15:02:00 on 2024-01-11
+        let text = [
+            r#"{"EventTimestampMs":1704985180778}"#,
+            r#"{"EventTimestampMs":1704985199999}"#,
+            r#"{"EventTimestampMs":1704985201000}"#,
+            r#"{"EventTimestampMs":1704985320000}"#,
+            r#"{"Outcome":"ok"}"#,
+        ]
+        .join("\n");
+        let counts = histogram(&text, Granularity::Minute, DEFAULT_TIMESTAMP_FIELD)
+            .into_iter()
+            .map(|(time, count)| (time.to_rfc3339_opts(SecondsFormat::Secs, true), count))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            counts,
+            [
+                ("2024-01-11T14:59:00Z".to_string(), 2),
+                ("2024-01-11T15:00:00Z".to_string(), 1),
+                ("2024-01-11T15:02:00Z".to_string(), 1),
+            ]
+        );
+        let counts = histogram(&text, Granularity::Hour, DEFAULT_TIMESTAMP_FIELD);
+        assert_eq!(
+            counts.iter().map(|(_, count)| *count).collect::<Vec<_>>(),
+            [2, 2]
+        );
+
+        let rendered = render_histogram(&histogram(
+            &text,
+            Granularity::Minute,
+            DEFAULT_TIMESTAMP_FIELD,
+        ));
+        let lines = rendered.lines().collect::<Vec<_>>();
+        assert_eq!(
+            lines[0],
+            format!("2024-01-11T14:59:00Z  {}  2", "#".repeat(HISTOGRAM_WIDTH))
+        );
+        assert!(lines[1].starts_with(&format!("2024-01-11T15:00:00Z  {} ", "#".repeat(25))));
+        assert!(lines[1].ends_with("  1"));
+    }
+
+    #[test]
+    fn test_boundary_records() {
+        let mut boundary = BoundaryRecords::new(DEFAULT_TIMESTAMP_FIELD);
+        // 2024-01-11T16:00:00Z and half a second later
+        let at_boundary = "{\"EventTimestampMs\":1704988800000}";
+        let after_boundary = "{\"EventTimestampMs\":1704988800500}";
+        let before = "{\"EventTimestampMs\":1704988700000}";
+        boundary.next_window("2024-01-11T15:00:00Z", "2024-01-11T16:00:00Z");
+        assert!([before, before, at_boundary, "not json", "{}"]
+            .iter()
+            .all(|line| boundary.keep(line)));
+        boundary.next_window("2024-01-11T16:00:00Z", "2024-01-11T17:00:00Z");
+        // the boundary record is dropped once, identical records away from it are kept
+        assert!(!boundary.keep(at_boundary));
+        assert!(boundary.keep(at_boundary));
+        assert!(boundary.keep(after_boundary));
+        assert!(boundary.keep(before));
+        assert!(boundary.keep("not json"));
+        assert!(boundary.keep("{}"));
+        assert_eq!(boundary.repeated, 1);
+    }
+
+    #[test]
+    fn test_boundary_dedup() {
+        let mut out = Vec::new();
+        let mut dedup = BoundaryDedup::new(&mut out, DEFAULT_TIMESTAMP_FIELD);
+        let at_boundary = "{\"EventTimestampMs\":1704988800000}";
+        dedup
+            .next_window("2024-01-11T15:00:00Z", "2024-01-11T16:00:00Z")
+            .unwrap();
+        // a line split across writes, and a window without a trailing newline
+        dedup.write_all(b"{\"n\":1}\n{\"EventTimest").unwrap();
+        dedup.write_all(b"ampMs\":1704988800000}").unwrap();
+        dedup
+            .next_window("2024-01-11T16:00:00Z", "2024-01-11T17:00:00Z")
+            .unwrap();
+        dedup
+            .write_all(format!("{}\n{{\"n\":1}}\n", at_boundary).as_bytes())
+            .unwrap();
+        dedup.finish().unwrap();
+        assert!(!dedup.is_closed());
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            format!("{{\"n\":1}}\n{}\n{{\"n\":1}}\n", at_boundary)
+        );
+    }
+
+    #[test]
+    fn test_timestamp_bounds() {
+        let args = ParsedArgs {
+            min_timestamp: DateTime::from_timestamp_millis(1704985181000),
+            max_timestamp: DateTime::from_timestamp_millis(1704985183000),
+            ..Default::default()
+        };
+        let text = [
+            r#"{"EventTimestampMs":1704985180999,"id":1}"#,
+            r#"{"EventTimestampMs":1704985181000,"id":2}"#,
+            r#"{"EventTimestampMs":1704985182000,"id":3}"#,
+            r#"{"EventTimestampMs":1704985183000,"id":4}"#,
+            r#"{"EventTimestampMs":1704985183001,"id":5}"#,
+            r#"{"id":6}"#,
+        ]
+        .join("\n");
+        let ids = process(&text, &args)
+            .lines()
+            .map(|line| serde_json::from_str::<Value>(line).unwrap()["id"].clone())
+            .collect::<Vec<_>>();
+        // both bounds are inclusive, records without a timestamp are kept
+        assert_eq!(ids, [2, 3, 4, 6]);
+    }
+
+    #[test]
+    fn test_redact_urls() {
+        let args = ParsedArgs {
+            redact_urls: true,
+            ..Default::default()
+        };
+        let text = [
+            r#"{"Event":{"Request":{"URL":"https://example.com/login?token=secret&user=a#top","Method":"GET"}}}"#,
+            r#"{"Event":{"Request":{"URL":"https://example.com/health"}}}"#,
+            r#"{"Outcome":"ok"}"#,
+        ]
+        .join("\n");
+        assert_eq!(
+            process(&text, &args),
+            [
+                r#"{"Event":{"Request":{"URL":"https://example.com/login","Method":"GET"}}}"#,
+                r#"{"Event":{"Request":{"URL":"https://example.com/health"}}}"#,
+                r#"{"Outcome":"ok"}"#,
+            ]
+            .join("\n")
+        );
+    }
+
+    #[test]
+    fn test_distinct() {
+        let text = [
+            r#"{"Event":{"Response":{"Status":500}}}"#,
+            r#"{"Event":{"Response":{"Status":200}}}"#,
+            r#"{"Event":{"Request":{"Method":"GET"}}}"#,
+            r#"{"Event":{"Response":{"Status":1000}}}"#,
+            r#"{"Event":{"Response":{"Status":200}}}"#,
+            "not json",
+        ]
+        .join("\n");
+        assert_eq!(distinct(&text, "Event.Response.Status"), "200\n500\n1000");
+        assert_eq!(distinct(&text, "Event.Request.Method"), "GET");
+        assert_eq!(distinct(&text, "Missing"), "");
+    }
+
+    #[test]
+    fn test_write_exceptions() {
+        let failed = r#"{"Event":{"RayID":"8443c2c1ba2c4ef1"},"EventTimestampMs":1704985180778,"Exceptions":[{"Name":"TypeError","Message":"x is undefined","Stack":"at handler (worker.js:3:7)\n  at fetch (worker.js:9:1)","TimestampMs":1704985180900}],"Outcome":"exception","ScriptName":"worker"}"#;
+        let text = [EVENT, failed, "not json"].join("\n");
+        let mut out = Vec::new();
+        assert_eq!(write_exceptions(&text, &mut out, false).unwrap(), 1);
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "\x1b[31m=== Exception in worker (RayID 8443c2c1ba2c4ef1) at 2024-01-11T14:59:40.900Z ===\n\
+             TypeError: x is undefined\n    \
+             at handler (worker.js:3:7)\n    \
+             at fetch (worker.js:9:1)\n\x1b[0m"
+        );
+    }
+
+    #[test]
+    fn test_write_exceptions_compact() {
+        let failed = |ray_id: &str, message: &str| {
+            format!(
+                r#"{{"Event":{{"RayID":"{}"}},"Exceptions":[{{"Name":"Error","Message":"{}"}}],"ScriptName":"worker"}}"#,
+                ray_id, message
+            )
+        };
+        let text = [
+            failed("1", "boom"),
+            failed("2", "boom"),
+            failed("3", "boom"),
+            failed("4", "other"),
+        ]
+        .join("\n");
+        let mut out = Vec::new();
+        assert_eq!(write_exceptions(&text, &mut out, true).unwrap(), 4);
+        let out = String::from_utf8(out).unwrap();
+        let messages = out
+            .lines()
+            .filter(|line| line.starts_with("Error"))
+            .collect::<Vec<_>>();
+        assert_eq!(messages, vec!["Error: boom (x3)", "Error: other"]);
+        assert!(out.contains("(RayID 1)") && !out.contains("(RayID 2)"));
+    }
+
+    #[test]
+    fn test_format_list_plain() {
+        assert_eq!(
+            format_list(LIST, ListFormat::Plain, None),
+            "20240111/a.log.gz\n20240111/bb.log.gz"
+        );
+    }
+
+    #[test]
+    fn test_format_list_json() {
+        let output = format_list(LIST, ListFormat::Json, None);
+        let objects = serde_json::from_str::<Value>(&output).unwrap();
+        assert_eq!(objects.as_array().unwrap().len(), 2);
+        assert_eq!(objects[0]["key"], "20240111/a.log.gz");
+        assert_eq!(objects[0]["size"], 1024);
+        assert_eq!(objects[1]["last_modified"], "2024-01-11T15:02:00Z");
+    }
+
+    #[test]
+    fn test_format_list_table() {
+        let output = format_list(LIST, ListFormat::Table, None);
+        let lines = output.lines().collect::<Vec<_>>();
+        assert_eq!(
+            lines,
+            vec![
+                "KEY                 SIZE  LAST_MODIFIED",
+                "20240111/a.log.gz   1024  2024-01-11T15:01:00Z",
+                "20240111/bb.log.gz     2  2024-01-11T15:02:00Z",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_format_list_humanized() {
+        let now = "2024-01-11T17:01:30Z".parse::<DateTime<Utc>>().unwrap();
+        let output = format_list(LIST, ListFormat::Table, Some(now));
+        let lines = output.lines().collect::<Vec<_>>();
+        assert_eq!(
+            lines[1],
+            "20240111/a.log.gz   1024  2024-01-11T15:01:00Z (2 hours ago)"
+        );
+        let time = "2024-01-11T15:00:00Z".parse::<DateTime<Utc>>().unwrap();
+        let since = |now: &str| humanize_since(time, now.parse().unwrap());
+        assert_eq!(since("2024-01-11T15:00:00Z"), "just now");
+        assert_eq!(since("2024-01-11T15:00:45Z"), "45 seconds ago");
+        assert_eq!(since("2024-01-11T15:01:59Z"), "1 minute ago");
+        assert_eq!(since("2024-01-13T16:00:00Z"), "2 days ago");
+        assert_eq!(since("2024-01-11T14:00:00Z"), "in the future");
+    }
+
+    #[test]
+    fn test_format_list_unexpected_shape() {
+        assert_eq!(format_list("not json", ListFormat::Json, None), "not json");
+    }
+}
@@ -0,0 +1,41 @@
+use directories::{BaseDirs, ProjectDirs};
+use std::path::PathBuf;
+
+/// ## Paths
+/// Platform-specific locations resolved via the `directories` crate:
+/// - Linux: `$XDG_CONFIG_HOME/r2logs` (default `~/.config`)
+/// - macOS: `~/Library/Application Support/r2logs`
+/// - Windows: `%APPDATA%\r2logs\config`
+fn project_dirs() -> Option<ProjectDirs> {
+    ProjectDirs::from("", "", "r2logs")
+}
+
+/// Directory holding the r2logs configuration
+pub fn config_dir() -> Option<PathBuf> {
+    project_dirs().map(|dirs| dirs.config_dir().to_path_buf())
+}
+
+/// Default configuration file, `config.toml` inside [`config_dir`]
+pub fn config_file() -> Option<PathBuf> {
+    config_dir().map(|dir| dir.join("config.toml"))
+}
+
+/// The user's home directory
+pub fn home_dir() -> Option<PathBuf> {
+    BaseDirs::new().map(|dirs| dirs.home_dir().to_path_buf())
+}
+
+#[cfg(test)]
+mod paths_tests {
+    use super::*;
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn test_xdg_paths() {
+        std::env::set_var("XDG_CONFIG_HOME", "/tmp/r2logs-xdg/config");
+        assert_eq!(
+            config_file(),
+            Some(PathBuf::from("/tmp/r2logs-xdg/config/r2logs/config.toml"))
+        );
+    }
+}
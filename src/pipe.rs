@@ -0,0 +1,65 @@
+use std::{
+    io::Write,
+    process::{Command, Stdio},
+};
+
+/// Runs `command` in the shell and writes `output` to its stdin, for `--post-command`.
+/// Fails when the command cannot be started, stops reading early or exits with a nonzero status.
+pub fn run(command: &str, output: &str) -> Result<(), String> {
+    let mut child = shell(command)
+        .stdin(Stdio::piped())
+        .spawn()
+        .map_err(|e| format!("Failed to run post command '{}': {}", command, e))?;
+    let written = child
+        .stdin
+        .take()
+        .map(|mut stdin| writeln!(stdin, "{}", output))
+        .transpose();
+    // stdin is closed above so the command sees the end of the output
+    let status = child
+        .wait()
+        .map_err(|e| format!("Failed to wait for post command '{}': {}", command, e))?;
+    if !status.success() {
+        return Err(format!("Post command '{}' failed: {}", command, status));
+    }
+    written.map_err(|e| format!("Failed to write to post command '{}': {}", command, e))?;
+    Ok(())
+}
+
+#[cfg(unix)]
+fn shell(command: &str) -> Command {
+    let mut shell = Command::new("sh");
+    shell.arg("-c").arg(command);
+    shell
+}
+
+#[cfg(windows)]
+fn shell(command: &str) -> Command {
+    let mut shell = Command::new("cmd");
+    shell.arg("/C").arg(command);
+    shell
+}
+
+#[cfg(all(test, unix))]
+mod pipe_tests {
+    use super::*;
+
+    #[test]
+    fn test_run() {
+        let path = std::env::temp_dir().join(format!("r2logs-pipe-{}.ndjson", std::process::id()));
+        let output = (1..=1000)
+            .map(|n| format!("{{\"n\":{}}}", n))
+            .collect::<Vec<_>>()
+            .join("\n");
+        run(&format!("cat > '{}'", path.display()), &output).unwrap();
+        let received = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(received, format!("{}\n", output));
+    }
+
+    #[test]
+    fn test_run_failing_command() {
+        let error = run("cat > /dev/null; exit 3", "{}").unwrap_err();
+        assert!(error.contains("failed"), "{}", error);
+    }
+}
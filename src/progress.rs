@@ -0,0 +1,211 @@
+use std::{
+    io::{IsTerminal, Write},
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use serde_json::json;
+
+/// Minimum time between two progress lines
+const PROGRESS_INTERVAL: Duration = Duration::from_millis(500);
+
+/// ## Progress
+/// Machine-readable progress for `--progress-fd`, for wrappers that show their own progress bar.
+/// While bodies are received, a JSON line `{"bytes":..,"done":false,"elapsed_ms":..,"lines":..}`
+/// is written at most every 500ms, and a last one with `"done":true` when the run ends.
+///
+/// In verbose mode the progress is instead shown on a terminal's stderr as one line
+/// overwritten in place, which is cleared when the run ends.
+#[derive(Default)]
+pub struct Progress {
+    writer: Option<Mutex<ProgressWriter>>,
+}
+
+/// How the progress is written
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum ProgressFormat {
+    JsonLines,
+    StatusLine,
+}
+
+struct ProgressWriter {
+    out: Box<dyn Write + Send>,
+    format: ProgressFormat,
+    interval: Duration,
+    started: Instant,
+    last_written: Option<Instant>,
+    bytes: usize,
+    lines: usize,
+}
+
+impl ProgressWriter {
+    fn write(&mut self, done: bool) {
+        // progress is best effort, a reader that went away must not fail the run
+        let _ = match self.format {
+            ProgressFormat::JsonLines => {
+                let line = json!({
+                    "bytes": self.bytes,
+                    "done": done,
+                    "elapsed_ms": self.started.elapsed().as_millis(),
+                    "lines": self.lines,
+                });
+                writeln!(self.out, "{}", line)
+            }
+            // return to the start of the line and clear it, leaving it empty when done
+            ProgressFormat::StatusLine if done => write!(self.out, "\r\x1b[2K"),
+            ProgressFormat::StatusLine => write!(
+                self.out,
+                "\r\x1b[2KReceived {} bytes, {} lines in {:.1}s",
+                self.bytes,
+                self.lines,
+                self.started.elapsed().as_secs_f64()
+            ),
+        }
+        .and_then(|_| self.out.flush());
+        self.last_written = Some(Instant::now());
+    }
+}
+
+impl Progress {
+    pub fn new(out: Box<dyn Write + Send>, interval: Duration) -> Self {
+        Self::with_format(out, ProgressFormat::JsonLines, interval)
+    }
+
+    /// The status line of `--verbose`, only shown when stderr is a terminal
+    /// so that redirected diagnostics are not filled with carriage returns
+    pub fn status_line() -> Self {
+        let stderr = std::io::stderr();
+        if !stderr.is_terminal() {
+            return Self::default();
+        }
+        Self::with_format(
+            Box::new(stderr),
+            ProgressFormat::StatusLine,
+            PROGRESS_INTERVAL,
+        )
+    }
+
+    fn with_format(out: Box<dyn Write + Send>, format: ProgressFormat, interval: Duration) -> Self {
+        Self {
+            writer: Some(Mutex::new(ProgressWriter {
+                out,
+                format,
+                interval,
+                started: Instant::now(),
+                last_written: None,
+                bytes: 0,
+                lines: 0,
+            })),
+        }
+    }
+
+    /// Writes the progress to an open file descriptor inherited from the parent process
+    #[cfg(unix)]
+    pub fn from_fd(fd: i32) -> Result<Self, String> {
+        use std::{fs::File, os::fd::FromRawFd};
+
+        if (0..=2).contains(&fd) {
+            return Err(format!(
+                "--progress-fd {} would mix progress into stdin, stdout or stderr, use 3 or above",
+                fd
+            ));
+        }
+        // SAFETY: the descriptor is handed to r2logs for its own use and not owned by anything else
+        let file = unsafe { File::from_raw_fd(fd) };
+        if let Err(e) = file.metadata() {
+            // the descriptor is not open, do not let `File` close it again
+            std::mem::forget(file);
+            return Err(format!(
+                "--progress-fd {} is not an open file descriptor: {}",
+                fd, e
+            ));
+        }
+        Ok(Self::new(Box::new(file), PROGRESS_INTERVAL))
+    }
+
+    #[cfg(not(unix))]
+    pub fn from_fd(_fd: i32) -> Result<Self, String> {
+        Err("--progress-fd is only supported on Unix".to_string())
+    }
+
+    /// Adds a received chunk, writing a progress line when the interval has passed since the previous one
+    pub fn record(&self, chunk: &[u8]) {
+        let Some(writer) = &self.writer else {
+            return;
+        };
+        let Ok(mut writer) = writer.lock() else {
+            return;
+        };
+        writer.bytes += chunk.len();
+        writer.lines += chunk.iter().filter(|byte| **byte == b'\n').count();
+        if writer
+            .last_written
+            .is_none_or(|last| last.elapsed() >= writer.interval)
+        {
+            writer.write(false);
+        }
+    }
+
+    /// Writes the final progress line
+    pub fn finish(&self) {
+        if let Some(Ok(mut writer)) = self.writer.as_ref().map(Mutex::lock) {
+            writer.write(true);
+        }
+    }
+}
+
+#[cfg(all(test, unix))]
+mod progress_tests {
+    use super::*;
+    use std::{io::Read, os::fd::IntoRawFd};
+
+    #[test]
+    fn test_progress_fd() {
+        let (mut reader, writer) = std::io::pipe().unwrap();
+        let mut progress = Progress::from_fd(writer.into_raw_fd()).unwrap();
+        if let Some(writer) = &mut progress.writer {
+            writer.get_mut().unwrap().interval = Duration::ZERO;
+        }
+        progress.record(b"{\"n\":1}\n{\"n\":2}\n");
+        progress.record(b"{\"n\":3}\n");
+        progress.finish();
+        drop(progress);
+
+        let mut text = String::new();
+        reader.read_to_string(&mut text).unwrap();
+        let lines = text
+            .lines()
+            .map(|line| serde_json::from_str::<serde_json::Value>(line).unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(lines.len(), 3);
+        assert_eq!(
+            (lines[0]["bytes"].as_u64(), lines[0]["lines"].as_u64()),
+            (Some(16), Some(2))
+        );
+        assert_eq!(
+            (lines[2]["bytes"].as_u64(), lines[2]["lines"].as_u64()),
+            (Some(24), Some(3))
+        );
+        assert_eq!(lines[2]["done"], true);
+        assert!(Progress::from_fd(1).is_err());
+    }
+
+    #[test]
+    fn test_status_line() {
+        let (mut reader, writer) = std::io::pipe().unwrap();
+        let progress =
+            Progress::with_format(Box::new(writer), ProgressFormat::StatusLine, Duration::ZERO);
+        progress.record(b"{\"n\":1}\n{\"n\":2}\n");
+        progress.finish();
+        drop(progress);
+
+        let mut text = String::new();
+        reader.read_to_string(&mut text).unwrap();
+        assert!(!text.contains('\n'));
+        let updates = text.split('\r').collect::<Vec<_>>();
+        assert_eq!(updates[0], "");
+        assert!(updates[1].starts_with("\x1b[2KReceived 16 bytes, 2 lines in "));
+        // the line is cleared at the end
+        assert_eq!(updates[2], "\x1b[2K");
+    }
+}
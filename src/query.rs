@@ -0,0 +1,127 @@
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Deserializer};
+use std::{fs, path::Path};
+
+use crate::commands::parse_duration;
+
+/// ## Query File
+/// A reusable query loaded with `--query-file`, in TOML or, for a `.json` file, JSON.
+/// Flags given on the command line take precedence over its values.
+///
+/// ```toml
+/// start = "2024-01-11T15:00:00Z"
+/// duration = "5m"
+/// bucket = "worker-logs"
+/// field_order = ["EventTimestampMs", "Outcome"]
+/// flatten_logs = true
+/// ```
+#[derive(Deserialize, Debug, Clone, Default, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct QueryFile {
+    pub start: Option<DateTime<Utc>>,
+    pub end: Option<DateTime<Utc>>,
+    /// Length of the range from the start, e.g. 5m, instead of `end`
+    #[serde(default, deserialize_with = "deserialize_duration")]
+    pub duration: Option<Duration>,
+    /// Overrides `BUCKET_NAME`
+    pub bucket: Option<String>,
+    #[serde(default)]
+    pub field_order: Vec<String>,
+    #[serde(default)]
+    pub flatten_logs: bool,
+    #[serde(default)]
+    pub strip_ansi: bool,
+    #[serde(default)]
+    pub sanitize: bool,
+    #[serde(default)]
+    pub canonical: bool,
+}
+
+fn deserialize_duration<'de, D>(deserializer: D) -> Result<Option<Duration>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let duration = String::deserialize(deserializer)?;
+    parse_duration(&duration)
+        .map(Some)
+        .map_err(serde::de::Error::custom)
+}
+
+/// Reads and validates the query file at `path`
+pub fn load(path: &Path) -> Result<QueryFile, String> {
+    let text = fs::read_to_string(path)
+        .map_err(|e| format!("cannot read query file {}: {}", path.display(), e))?;
+    let is_json = path
+        .extension()
+        .is_some_and(|extension| extension.eq_ignore_ascii_case("json"));
+    let query = if is_json {
+        serde_json::from_str::<QueryFile>(&text).map_err(|e| e.to_string())
+    } else {
+        toml::from_str::<QueryFile>(&text).map_err(|e| e.to_string())
+    }
+    .map_err(|e| format!("invalid query file {}: {}", path.display(), e))?;
+    if query.end.is_some() && query.duration.is_some() {
+        return Err(format!(
+            "invalid query file {}: set either end or duration",
+            path.display()
+        ));
+    }
+    Ok(query)
+}
+
+#[cfg(test)]
+mod query_tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn write_query(name: &str, text: &str) -> std::path::PathBuf {
+        let path = std::env::temp_dir().join(format!("r2logs-{}-{}", std::process::id(), name));
+        fs::write(&path, text).unwrap();
+        path
+    }
+
+    #[test]
+    fn test_load() {
+        let toml = write_query(
+            "query.toml",
+            "start = \"2024-01-11T15:00:00Z\"\nduration = \"5m\"\nbucket = \"worker-logs\"\nfield_order = [\"Outcome\"]\nflatten_logs = true\n",
+        );
+        let json = write_query(
+            "query.json",
+            r#"{"start":"2024-01-11T15:00:00Z","duration":"5m","bucket":"worker-logs","field_order":["Outcome"],"flatten_logs":true}"#,
+        );
+        for path in [toml, json] {
+            let query = load(&path).unwrap();
+            fs::remove_file(&path).unwrap();
+            assert_eq!(
+                query,
+                QueryFile {
+                    start: Some(Utc.with_ymd_and_hms(2024, 1, 11, 15, 0, 0).unwrap()),
+                    duration: Some(Duration::minutes(5)),
+                    bucket: Some("worker-logs".to_string()),
+                    field_order: vec!["Outcome".to_string()],
+                    flatten_logs: true,
+                    ..Default::default()
+                }
+            );
+        }
+    }
+
+    #[test]
+    fn test_load_invalid() {
+        for (name, text) in [
+            ("unknown.toml", "filter = \"x\"\n"),
+            ("duration.toml", "duration = \"soon\"\n"),
+            (
+                "range.toml",
+                "end = \"2024-01-11T15:05:00Z\"\nduration = \"5m\"\n",
+            ),
+        ] {
+            let path = write_query(name, text);
+            let result = load(&path);
+            fs::remove_file(&path).unwrap();
+            assert!(result.is_err(), "{}", name);
+        }
+        assert!(load(Path::new("/nonexistent/query.toml")).is_err());
+    }
+}
@@ -0,0 +1,166 @@
+use serde_json::{json, Value};
+use std::{fs, io, path::Path, time::Duration};
+
+use crate::api::FetchStats;
+use crate::ParsedArgs;
+
+/// ## Report
+/// The summary of a run written by `--report`, whether it succeeded or not:
+/// the exit status, time range, bucket, what was fetched and the errors encountered.
+#[derive(Debug, Default)]
+pub struct Report {
+    start_time: String,
+    end_time: String,
+    bucket: Option<String>,
+    objects: usize,
+    bytes: usize,
+    /// `None` when the logs were streamed without being counted
+    lines: Option<usize>,
+    retries: u32,
+    errors: Vec<String>,
+}
+
+impl Report {
+    pub fn new(args: &ParsedArgs) -> Self {
+        Self {
+            start_time: args.start_time.clone(),
+            end_time: args.end_time.clone(),
+            bucket: args.bucket.clone(),
+            ..Default::default()
+        }
+    }
+
+    /// The bucket queried, once the environment is known
+    pub fn set_bucket(&mut self, bucket: &str) {
+        self.bucket = Some(bucket.to_string());
+    }
+
+    /// Records the requests of the run and the lines output, noting the objects that failed
+    /// and a rejected request
+    pub fn record(&mut self, stats: &FetchStats, lines: Option<usize>) {
+        self.objects = stats.sources.len();
+        self.bytes = stats.bytes;
+        self.lines = lines;
+        self.retries = stats.retries;
+        self.errors.extend(
+            stats
+                .failed
+                .iter()
+                .map(|key| format!("Failed to fetch {}", key)),
+        );
+        if let Some(status) = stats.status.filter(|_| !stats.is_success()) {
+            self.errors
+                .push(format!("Request rejected with status {}", status));
+        }
+    }
+
+    /// Prints a failure that ends the run to stderr and records it, returning the exit code 1
+    pub fn fail(&mut self, message: String) -> i32 {
+        eprintln!("{}", message);
+        self.errors.push(message);
+        1
+    }
+
+    /// Records an error printed elsewhere
+    pub fn add_error(&mut self, message: String) {
+        self.errors.push(message);
+    }
+
+    pub fn to_json(&self, exit_code: i32, duration: Duration) -> Value {
+        json!({
+            "bucket": self.bucket,
+            "bytes": self.bytes,
+            "duration_ms": duration.as_millis() as u64,
+            "end": self.end_time,
+            "errors": self.errors,
+            "exit_code": exit_code,
+            "lines": self.lines,
+            "objects": self.objects,
+            "retries": self.retries,
+            "start": self.start_time,
+        })
+    }
+
+    /// Writes the report as a JSON object to `path`, replacing the file
+    pub fn write(&self, path: &Path, exit_code: i32, duration: Duration) -> io::Result<()> {
+        fs::write(path, format!("{}\n", self.to_json(exit_code, duration)))
+    }
+}
+
+#[cfg(test)]
+mod report_tests {
+    use super::*;
+
+    const KEYS: [&str; 10] = [
+        "bucket",
+        "bytes",
+        "duration_ms",
+        "end",
+        "errors",
+        "exit_code",
+        "lines",
+        "objects",
+        "retries",
+        "start",
+    ];
+
+    fn read(path: &Path) -> Value {
+        let report = serde_json::from_str::<Value>(&fs::read_to_string(path).unwrap()).unwrap();
+        let keys = report.as_object().unwrap().keys().collect::<Vec<_>>();
+        assert_eq!(keys, KEYS);
+        report
+    }
+
+    #[test]
+    fn test_write() {
+        let path = std::env::temp_dir().join(format!("r2logs-report-{}.json", std::process::id()));
+        let args = ParsedArgs {
+            start_time: "2024-01-11T15:00:00Z".to_string(),
+            end_time: "2024-01-11T15:05:00Z".to_string(),
+            ..Default::default()
+        };
+
+        let mut report = Report::new(&args);
+        report.set_bucket("worker-logs");
+        let stats = FetchStats {
+            status: Some(200),
+            retries: 1,
+            bytes: 42,
+            sources: vec![("20240111/a.log.gz".to_string(), 42)],
+            ..Default::default()
+        };
+        report.record(&stats, Some(2));
+        report.write(&path, 0, Duration::from_millis(1500)).unwrap();
+        let success = read(&path);
+        assert_eq!(success["exit_code"], 0);
+        assert_eq!(success["start"], "2024-01-11T15:00:00Z");
+        assert_eq!(success["bucket"], "worker-logs");
+        assert_eq!(success["objects"], 1);
+        assert_eq!(success["lines"], 2);
+        assert_eq!(success["duration_ms"], 1500);
+        assert_eq!(success["errors"], json!([]));
+
+        let mut report = Report::new(&args);
+        let stats = FetchStats {
+            status: Some(403),
+            failed: vec!["20240111/b.log.gz".to_string()],
+            ..Default::default()
+        };
+        report.record(&stats, None);
+        assert_eq!(report.fail("Failed to write the output".to_string()), 1);
+        report.write(&path, 1, Duration::ZERO).unwrap();
+        let failure = read(&path);
+        assert_eq!(failure["exit_code"], 1);
+        assert_eq!(failure["bucket"], Value::Null);
+        assert_eq!(failure["lines"], Value::Null);
+        assert_eq!(
+            failure["errors"],
+            json!([
+                "Failed to fetch 20240111/b.log.gz",
+                "Request rejected with status 403",
+                "Failed to write the output"
+            ])
+        );
+        fs::remove_file(&path).unwrap();
+    }
+}
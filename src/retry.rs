@@ -0,0 +1,263 @@
+use chrono::{DateTime, Utc};
+use std::{
+    sync::atomic::{AtomicU32, Ordering},
+    time::Duration,
+};
+
+/// Maximum number of attempts for a request failing with a retryable transport error
+pub const CONNECT_RETRY_ATTEMPTS: u32 = 3;
+/// Retries of a request rejected with a transient status, unless `--max-retries` is given
+pub const DEFAULT_MAX_RETRIES: u32 = 3;
+/// Delay before the first retry, doubled on every following attempt
+const BASE_BACKOFF: Duration = Duration::from_millis(500);
+/// Longest wait before a retry, whether from the backoff or a `Retry-After` header
+pub const MAX_RETRY_WAIT: Duration = Duration::from_secs(60);
+
+/// ## Transport Error
+/// Predicates used to classify a failed request.
+/// Implemented for `reqwest::Error` so the retry decision can be tested with a fake error.
+pub trait TransportError {
+    fn is_timeout(&self) -> bool;
+    fn is_connect(&self) -> bool;
+}
+
+impl TransportError for reqwest::Error {
+    fn is_timeout(&self) -> bool {
+        reqwest::Error::is_timeout(self)
+    }
+    fn is_connect(&self) -> bool {
+        reqwest::Error::is_connect(self)
+    }
+}
+
+/// ## Error Kinds
+/// - `Timeout`: the request timed out, always retried
+/// - `Connect`: DNS, TCP or TLS failure while connecting, retried unless `--retry-on-connect-error false`
+/// - `Other`: any other error (building the request, decoding the body, ...), never retried
+#[derive(Debug, PartialEq)]
+pub enum ErrorKind {
+    Timeout,
+    Connect,
+    Other,
+}
+
+pub fn classify(err: &impl TransportError) -> ErrorKind {
+    if err.is_timeout() {
+        ErrorKind::Timeout
+    } else if err.is_connect() {
+        ErrorKind::Connect
+    } else {
+        ErrorKind::Other
+    }
+}
+
+pub fn should_retry(err: &impl TransportError, retry_on_connect_error: bool) -> bool {
+    match classify(err) {
+        ErrorKind::Timeout => true,
+        ErrorKind::Connect => retry_on_connect_error,
+        ErrorKind::Other => false,
+    }
+}
+
+/// Exponential backoff before the given (1-based) retry attempt, capped at `MAX_RETRY_WAIT`
+pub fn backoff(attempt: u32) -> Duration {
+    2u32.checked_pow(attempt.saturating_sub(1))
+        .and_then(|factor| BASE_BACKOFF.checked_mul(factor))
+        .map_or(MAX_RETRY_WAIT, |wait| wait.min(MAX_RETRY_WAIT))
+}
+
+/// Whether a response status is transient and worth retrying: 429 and 500, 502, 503, 504.
+/// Other failures such as 401, 403 or 404 would fail again.
+pub fn is_retryable_status(status: u16) -> bool {
+    matches!(status, 429 | 500 | 502 | 503 | 504)
+}
+
+/// The wait requested by a `Retry-After` header, in seconds or as an HTTP date relative to `now`.
+/// A date in the past means no wait; an unreadable value is ignored.
+/// The wait is capped at `MAX_RETRY_WAIT` so a server cannot stall the run indefinitely.
+pub fn retry_after(value: &str, now: DateTime<Utc>) -> Option<Duration> {
+    let value = value.trim();
+    let wait = match value.parse::<u64>() {
+        Ok(seconds) => Duration::from_secs(seconds),
+        Err(_) => {
+            let date = DateTime::parse_from_rfc2822(value).ok()?;
+            (date.with_timezone(&Utc) - now)
+                .to_std()
+                .unwrap_or_default()
+        }
+    };
+    Some(wait.min(MAX_RETRY_WAIT))
+}
+
+/// Whether waiting `wait` more after `spent` on a request still fits in the `--retry-budget`
+pub fn within_budget(spent: Duration, wait: Duration, budget: Option<Duration>) -> bool {
+    budget.is_none_or(|budget| spent + wait <= budget)
+}
+
+/// ## Circuit Breaker
+/// Opens after `--circuit-threshold` consecutive failed requests, so the remaining ones are skipped
+/// instead of hammering a failing API. Any success closes it again; without a threshold it never opens.
+#[derive(Debug, Default)]
+pub struct CircuitBreaker {
+    threshold: Option<u32>,
+    consecutive_failures: AtomicU32,
+}
+
+impl CircuitBreaker {
+    pub fn new(threshold: Option<u32>) -> Self {
+        Self {
+            threshold,
+            ..Default::default()
+        }
+    }
+
+    /// Records the outcome of a request
+    pub fn record(&self, succeeded: bool) {
+        if succeeded {
+            self.consecutive_failures.store(0, Ordering::Relaxed);
+        } else {
+            self.consecutive_failures.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    pub fn is_open(&self) -> bool {
+        self.threshold
+            .is_some_and(|threshold| self.consecutive_failures.load(Ordering::Relaxed) >= threshold)
+    }
+
+    /// The error reported when the open circuit skips the remaining requests
+    pub fn open_error(&self, skipped: usize) -> String {
+        format!(
+            "Circuit open after {} consecutive failures, skipping {} remaining request(s)",
+            self.consecutive_failures.load(Ordering::Relaxed),
+            skipped
+        )
+    }
+}
+
+#[cfg(test)]
+mod retry_tests {
+    use super::*;
+
+    struct FakeError {
+        timeout: bool,
+        connect: bool,
+    }
+
+    impl TransportError for FakeError {
+        fn is_timeout(&self) -> bool {
+            self.timeout
+        }
+        fn is_connect(&self) -> bool {
+            self.connect
+        }
+    }
+
+    const TIMEOUT: FakeError = FakeError {
+        timeout: true,
+        connect: false,
+    };
+    const CONNECT: FakeError = FakeError {
+        timeout: false,
+        connect: true,
+    };
+    const OTHER: FakeError = FakeError {
+        timeout: false,
+        connect: false,
+    };
+
+    #[test]
+    fn test_classify() {
+        assert_eq!(classify(&TIMEOUT), ErrorKind::Timeout);
+        assert_eq!(classify(&CONNECT), ErrorKind::Connect);
+        assert_eq!(classify(&OTHER), ErrorKind::Other);
+    }
+
+    #[test]
+    fn test_should_retry_with_connect_error_retries() {
+        assert!(should_retry(&TIMEOUT, true));
+        assert!(should_retry(&CONNECT, true));
+        assert!(!should_retry(&OTHER, true));
+    }
+
+    #[test]
+    fn test_should_retry_without_connect_error_retries() {
+        assert!(should_retry(&TIMEOUT, false));
+        assert!(!should_retry(&CONNECT, false));
+        assert!(!should_retry(&OTHER, false));
+    }
+
+    #[test]
+    fn test_backoff() {
+        assert_eq!(backoff(1), Duration::from_millis(500));
+        assert_eq!(backoff(2), Duration::from_millis(1000));
+        assert_eq!(backoff(3), Duration::from_millis(2000));
+        assert_eq!(backoff(8), MAX_RETRY_WAIT);
+        assert_eq!(backoff(33), MAX_RETRY_WAIT);
+        assert_eq!(backoff(u32::MAX), MAX_RETRY_WAIT);
+    }
+
+    #[test]
+    fn test_is_retryable_status() {
+        assert!([429, 500, 502, 503, 504]
+            .into_iter()
+            .all(is_retryable_status));
+        assert!(![400, 401, 403, 404, 501]
+            .into_iter()
+            .any(is_retryable_status));
+    }
+
+    #[test]
+    fn test_retry_after() {
+        let now = DateTime::parse_from_rfc3339("2015-10-21T07:28:00Z")
+            .unwrap()
+            .with_timezone(&Utc);
+        assert_eq!(retry_after("45", now), Some(Duration::from_secs(45)));
+        assert_eq!(retry_after("120", now), Some(MAX_RETRY_WAIT));
+        assert_eq!(
+            retry_after("Wed, 21 Oct 2015 07:28:30 GMT", now),
+            Some(Duration::from_secs(30))
+        );
+        assert_eq!(
+            retry_after("Wed, 21 Oct 2015 07:27:00 GMT", now),
+            Some(Duration::ZERO)
+        );
+        assert_eq!(retry_after("soon", now), None);
+        assert_eq!(
+            retry_after(&u64::MAX.to_string(), now),
+            Some(MAX_RETRY_WAIT)
+        );
+        assert_eq!(
+            retry_after("Fri, 21 Oct 2095 07:28:00 GMT", now),
+            Some(MAX_RETRY_WAIT)
+        );
+    }
+
+    #[test]
+    fn test_within_budget() {
+        let second = Duration::from_secs(1);
+        assert!(within_budget(second * 100, second, None));
+        assert!(within_budget(second, second, Some(second * 2)));
+        assert!(!within_budget(
+            second,
+            backoff(2),
+            Some(second + backoff(1))
+        ));
+    }
+
+    #[test]
+    fn test_circuit_breaker() {
+        let breaker = CircuitBreaker::new(Some(2));
+        breaker.record(false);
+        assert!(!breaker.is_open());
+        breaker.record(true);
+        breaker.record(false);
+        assert!(!breaker.is_open());
+        breaker.record(false);
+        assert!(breaker.is_open());
+
+        let breaker = CircuitBreaker::new(None);
+        (0..10).for_each(|_| breaker.record(false));
+        assert!(!breaker.is_open());
+    }
+}
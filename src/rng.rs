@@ -0,0 +1,64 @@
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use std::{
+    sync::{Mutex, OnceLock},
+    time::Duration,
+};
+
+/// Shared RNG used by every randomized behavior (e.g. retry jitter)
+static RNG: OnceLock<Mutex<StdRng>> = OnceLock::new();
+
+/// Seeds the shared RNG with `--seed`, or from entropy when no seed is given.
+/// Must be called before the first random draw to take effect.
+pub fn init(seed: Option<u64>) {
+    let _ = RNG.set(Mutex::new(new_rng(seed)));
+}
+
+fn new_rng(seed: Option<u64>) -> StdRng {
+    match seed {
+        Some(seed) => StdRng::seed_from_u64(seed),
+        None => StdRng::from_entropy(),
+    }
+}
+
+/// A random duration in `[0, max)` drawn from the shared RNG
+pub fn jitter(max: Duration) -> Duration {
+    let rng = RNG.get_or_init(|| Mutex::new(new_rng(None)));
+    let mut rng = rng.lock().unwrap_or_else(|e| e.into_inner());
+    jitter_with(&mut *rng, max)
+}
+
+fn jitter_with(rng: &mut impl Rng, max: Duration) -> Duration {
+    if max.is_zero() {
+        return Duration::ZERO;
+    }
+    Duration::from_nanos(rng.gen_range(0..max.as_nanos() as u64))
+}
+
+#[cfg(test)]
+mod rng_tests {
+    use super::*;
+
+    fn jitter_sequence(seed: u64) -> Vec<Duration> {
+        let mut rng = new_rng(Some(seed));
+        (0..10)
+            .map(|_| jitter_with(&mut rng, Duration::from_secs(1)))
+            .collect()
+    }
+
+    #[test]
+    fn test_same_seed_produces_same_jitter() {
+        assert_eq!(jitter_sequence(42), jitter_sequence(42));
+        assert_ne!(jitter_sequence(42), jitter_sequence(43));
+    }
+
+    #[test]
+    fn test_jitter_is_bounded() {
+        let max = Duration::from_millis(250);
+        assert!(jitter_sequence(7)
+            .iter()
+            .all(|d| *d < Duration::from_secs(1)));
+        let mut rng = new_rng(Some(7));
+        assert!(jitter_with(&mut rng, max) < max);
+        assert_eq!(jitter_with(&mut rng, Duration::ZERO), Duration::ZERO);
+    }
+}
@@ -0,0 +1,272 @@
+use std::{
+    collections::BTreeMap,
+    fs::{self, File, OpenOptions},
+    io::{self, Write},
+    path::{Path, PathBuf},
+};
+
+use serde_json::Value;
+
+use crate::commands::SplitBy;
+use crate::output;
+
+/// File for records without a readable timestamp
+const UNKNOWN_FILE_STEM: &str = "unknown";
+
+/// Appends each record to `DIR/<bucket>.ndjson`, the bucket being the day (`YYYY-MM-DD`)
+/// or hour (`YYYY-MM-DDTHH`) of its timestamp at the dotted `timestamp_field`.
+/// With `rotate_bytes`, records go to numbered parts `DIR/<bucket>.<NNN>.ndjson` instead (see [`write_parts`]).
+/// Returns the files written with their number of records, in file name order.
+pub fn write_records(
+    text: &str,
+    dir: &Path,
+    split_by: SplitBy,
+    timestamp_field: &str,
+    rotate_bytes: Option<u64>,
+) -> io::Result<Vec<(PathBuf, usize)>> {
+    let mut buckets = BTreeMap::<String, Vec<&str>>::new();
+    for line in text.lines().filter(|line| !line.trim().is_empty()) {
+        let stem = serde_json::from_str::<Value>(line)
+            .ok()
+            .and_then(|record| output::event_time(&record, timestamp_field))
+            .map_or(UNKNOWN_FILE_STEM.to_string(), |time| {
+                split_by.file_stem(time)
+            });
+        buckets.entry(stem).or_default().push(line);
+    }
+
+    fs::create_dir_all(dir)?;
+    let mut written = Vec::new();
+    for (stem, lines) in buckets {
+        if let Some(limit) = rotate_bytes {
+            written.extend(write_parts(dir, &stem, &lines, limit)?);
+            continue;
+        }
+        let path = dir.join(format!("{}.ndjson", stem));
+        let mut file = OpenOptions::new().create(true).append(true).open(&path)?;
+        for line in &lines {
+            writeln!(file, "{}", line)?;
+        }
+        written.push((path, lines.len()));
+    }
+    Ok(written)
+}
+
+fn part_path(dir: &Path, stem: &str, part: usize) -> PathBuf {
+    dir.join(format!("{}.{:03}.ndjson", stem, part))
+}
+
+/// Appends the lines to the last part of the bucket, starting a new part whenever the next line
+/// would take the current one past `limit` bytes. A line larger than the limit gets a part of its own.
+fn write_parts(
+    dir: &Path,
+    stem: &str,
+    lines: &[&str],
+    limit: u64,
+) -> io::Result<Vec<(PathBuf, usize)>> {
+    // continue the last part of a previous run
+    let mut part = 1;
+    while part_path(dir, stem, part + 1).exists() {
+        part += 1;
+    }
+    let mut size = fs::metadata(part_path(dir, stem, part)).map_or(0, |metadata| metadata.len());
+    let mut file: Option<File> = None;
+    let mut written = Vec::<(PathBuf, usize)>::new();
+    for line in lines {
+        let line_bytes = line.len() as u64 + 1;
+        if size > 0 && size + line_bytes > limit {
+            // dropping the current part closes it before the next one is opened
+            file = None;
+            part += 1;
+            size = 0;
+        }
+        if file.is_none() {
+            let path = part_path(dir, stem, part);
+            file = Some(OpenOptions::new().create(true).append(true).open(&path)?);
+            written.push((path, 0));
+        }
+        writeln!(file.as_mut().expect("part is open"), "{}", line)?;
+        size += line_bytes;
+        if let Some((_, records)) = written.last_mut() {
+            *records += 1;
+        }
+    }
+    Ok(written)
+}
+
+/// The numbered part of an `--output` file rotated by `--rotate-bytes`, the number before the extension,
+/// e.g. `logs.002.ndjson` for `logs.ndjson` or `logs.002` for `logs`
+pub fn rotated_path(path: &Path, part: usize) -> PathBuf {
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    let name = match path.extension() {
+        Some(extension) => format!("{}.{:03}.{}", stem, part, extension.to_string_lossy()),
+        None => format!("{}.{:03}", stem, part),
+    };
+    path.with_file_name(name)
+}
+
+/// ## Rotating File
+/// The `--output` writer of `--rotate-bytes`: lines are written to the numbered parts of the file
+/// (see [`rotated_path`]) as soon as they are complete, so the logs are flushed mid-stream, and a new part
+/// is started whenever the next line would take the current one past the limit, like [`write_parts`].
+/// A last line without a newline is written when the writer is dropped.
+pub struct RotatingFile {
+    path: PathBuf,
+    limit: u64,
+    part: usize,
+    size: u64,
+    file: File,
+    partial: Vec<u8>,
+}
+
+impl RotatingFile {
+    /// Rotates `path` starting with `first`, its already created first part
+    pub fn new(path: &Path, limit: u64, first: File) -> Self {
+        Self {
+            path: path.to_path_buf(),
+            limit,
+            part: 1,
+            size: 0,
+            file: first,
+            partial: Vec::new(),
+        }
+    }
+
+    fn write_line(&mut self, line: &[u8]) -> io::Result<()> {
+        let line_bytes = line.len() as u64;
+        if self.size > 0 && self.size + line_bytes > self.limit {
+            self.part += 1;
+            self.file = File::create(rotated_path(&self.path, self.part))?;
+            self.size = 0;
+        }
+        self.file.write_all(line)?;
+        self.size += line_bytes;
+        Ok(())
+    }
+}
+
+impl Write for RotatingFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.partial.extend_from_slice(buf);
+        while let Some(end) = self.partial.iter().position(|byte| *byte == b'\n') {
+            let line = self.partial.drain(..=end).collect::<Vec<_>>();
+            self.write_line(&line)?;
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+impl Drop for RotatingFile {
+    fn drop(&mut self) {
+        let line = std::mem::take(&mut self.partial);
+        if !line.is_empty() {
+            // nothing is left to report an error to
+            let _ = self.write_line(&line);
+        }
+    }
+}
+
+#[cfg(test)]
+mod split_tests {
+    use super::*;
+
+    #[test]
+    fn test_write_records_by_hour() {
+        let dir = std::env::temp_dir().join(format!("r2logs-split-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        // 14:59:40 and 15:00:01 on 2024-01-11
+        let text = [
+            r#"{"EventTimestampMs":1704985180778,"n":1}"#,
+            r#"{"EventTimestampMs":1704985201000,"n":2}"#,
+            r#"{"EventTimestampMs":1704985180999,"n":3}"#,
+            "not json",
+        ]
+        .join("\n");
+
+        let written = write_records(&text, &dir, SplitBy::Hour, "EventTimestampMs", None).unwrap();
+        assert_eq!(
+            written,
+            vec![
+                (dir.join("2024-01-11T14.ndjson"), 2),
+                (dir.join("2024-01-11T15.ndjson"), 1),
+                (dir.join("unknown.ndjson"), 1),
+            ]
+        );
+        assert_eq!(
+            fs::read_to_string(dir.join("2024-01-11T14.ndjson")).unwrap(),
+            "{\"EventTimestampMs\":1704985180778,\"n\":1}\n{\"EventTimestampMs\":1704985180999,\"n\":3}\n"
+        );
+
+        let written = write_records(&text, &dir, SplitBy::Day, "EventTimestampMs", None).unwrap();
+        assert_eq!(written[0], (dir.join("2024-01-11.ndjson"), 3));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_write_records_rotated() {
+        let dir = std::env::temp_dir().join(format!("r2logs-rotate-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        // 41 bytes per line with its newline
+        let line = |n: usize| format!(r#"{{"EventTimestampMs":1704985180778,"n":{}}}"#, n);
+        let text = (1..=5).map(line).collect::<Vec<_>>().join("\n");
+
+        let written =
+            write_records(&text, &dir, SplitBy::Day, "EventTimestampMs", Some(100)).unwrap();
+        let part = |n: usize| dir.join(format!("2024-01-11.{:03}.ndjson", n));
+        assert_eq!(written, vec![(part(1), 2), (part(2), 2), (part(3), 1)]);
+        assert_eq!(
+            fs::read_to_string(part(1)).unwrap(),
+            format!("{}\n{}\n", line(1), line(2))
+        );
+        assert!((1..=3).all(|n| fs::metadata(part(n)).unwrap().len() <= 100));
+
+        // a later run fills the last part before rotating again
+        let written =
+            write_records(&text, &dir, SplitBy::Day, "EventTimestampMs", Some(100)).unwrap();
+        assert_eq!(written, vec![(part(3), 1), (part(4), 2), (part(5), 2)]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_rotating_file() {
+        let dir = std::env::temp_dir().join(format!("r2logs-rotate-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("logs.ndjson");
+        assert_eq!(rotated_path(&path, 2), dir.join("logs.002.ndjson"));
+        assert_eq!(rotated_path(&dir.join("logs"), 1), dir.join("logs.001"));
+
+        let first = File::create(rotated_path(&path, 1)).unwrap();
+        let mut out = RotatingFile::new(&path, 22, first);
+        // lines of 11 bytes, split across writes, and a last line without newline
+        out.write_all(b"{\"n\":1111}\n{\"n\":22").unwrap();
+        out.write_all(b"22}\n{\"n\":3333}\n").unwrap();
+        // the first part is written before the stream ends
+        assert_eq!(
+            fs::read_to_string(dir.join("logs.001.ndjson")).unwrap(),
+            "{\"n\":1111}\n{\"n\":2222}\n"
+        );
+        out.write_all(b"{\"n\":4444444444444444}\n{\"n\":5}")
+            .unwrap();
+        drop(out);
+        assert_eq!(
+            fs::read_to_string(dir.join("logs.002.ndjson")).unwrap(),
+            "{\"n\":3333}\n"
+        );
+        // a line larger than the limit gets a part of its own
+        assert_eq!(
+            fs::read_to_string(dir.join("logs.003.ndjson")).unwrap(),
+            "{\"n\":4444444444444444}\n"
+        );
+        assert_eq!(
+            fs::read_to_string(dir.join("logs.004.ndjson")).unwrap(),
+            "{\"n\":5}"
+        );
+        assert!(!dir.join("logs.005.ndjson").exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
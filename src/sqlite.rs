@@ -0,0 +1,136 @@
+use rusqlite::{types::Value as SqlValue, Connection};
+use serde_json::Value;
+use std::path::Path;
+
+use crate::fields;
+
+/// Table the records are inserted into
+const TABLE: &str = "logs";
+
+/// Inserts each JSON record of `text` into the `logs` table of the database at `path`, for `--sqlite`.
+/// With `--fields` each field becomes a column named after its dotted path,
+/// otherwise the whole record is stored in a `json` column.
+/// The table is created on first use; lines that are not valid JSON are skipped.
+/// Returns the number of inserted rows.
+pub fn insert_records(path: &Path, text: &str, columns: &[String]) -> rusqlite::Result<usize> {
+    let mut connection = Connection::open(path)?;
+    let whole_record = columns.is_empty();
+    let columns = if whole_record {
+        vec!["json".to_string()]
+    } else {
+        columns.to_vec()
+    };
+    let quoted = columns
+        .iter()
+        .map(|column| quote(column))
+        .collect::<Vec<_>>();
+    connection.execute(
+        &format!(
+            "CREATE TABLE IF NOT EXISTS {} ({})",
+            TABLE,
+            quoted.join(", ")
+        ),
+        (),
+    )?;
+
+    let transaction = connection.transaction()?;
+    let mut inserted = 0;
+    {
+        let placeholders = vec!["?"; columns.len()].join(", ");
+        let mut statement = transaction.prepare(&format!(
+            "INSERT INTO {} ({}) VALUES ({})",
+            TABLE,
+            quoted.join(", "),
+            placeholders
+        ))?;
+        for record in text
+            .lines()
+            .filter_map(|line| serde_json::from_str::<Value>(line).ok())
+        {
+            let values = if whole_record {
+                vec![SqlValue::Text(record.to_string())]
+            } else {
+                columns
+                    .iter()
+                    .map(|column| to_sql(fields::get(&record, column)))
+                    .collect()
+            };
+            statement.execute(rusqlite::params_from_iter(values))?;
+            inserted += 1;
+        }
+    }
+    transaction.commit()?;
+    Ok(inserted)
+}
+
+/// Quotes a column name, e.g. `Event.RayID` becomes `"Event.RayID"`
+fn quote(column: &str) -> String {
+    format!("\"{}\"", column.replace('"', "\"\""))
+}
+
+/// Maps a JSON value to its SQLite type, storing objects and arrays as JSON text
+fn to_sql(value: Option<&Value>) -> SqlValue {
+    match value {
+        None | Some(Value::Null) => SqlValue::Null,
+        Some(Value::Bool(b)) => SqlValue::Integer(i64::from(*b)),
+        Some(Value::Number(n)) => n
+            .as_i64()
+            .map(SqlValue::Integer)
+            .or_else(|| n.as_f64().map(SqlValue::Real))
+            .unwrap_or(SqlValue::Null),
+        Some(Value::String(s)) => SqlValue::Text(s.clone()),
+        Some(value) => SqlValue::Text(value.to_string()),
+    }
+}
+
+#[cfg(test)]
+mod sqlite_tests {
+    use super::*;
+
+    const RECORDS: &str = r#"{"EventTimestampMs":1704985180778,"Outcome":"ok","Event":{"RayID":"a"}}
+{"EventTimestampMs":1704985181064,"Outcome":"exception","Event":{"RayID":"b"}}
+not json"#;
+
+    #[test]
+    fn test_insert_records_with_fields() {
+        let path = std::env::temp_dir().join(format!("r2logs-fields-{}.db", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let columns = ["EventTimestampMs", "Event.RayID"].map(String::from);
+        assert_eq!(insert_records(&path, RECORDS, &columns).unwrap(), 2);
+        // the schema is reused on the next run
+        assert_eq!(insert_records(&path, RECORDS, &columns).unwrap(), 2);
+
+        let connection = Connection::open(&path).unwrap();
+        let rows = connection
+            .prepare(r#"SELECT "EventTimestampMs", "Event.RayID" FROM logs ORDER BY rowid"#)
+            .unwrap()
+            .query_map((), |row| {
+                Ok((row.get::<_, i64>(0)?, row.get::<_, String>(1)?))
+            })
+            .unwrap()
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(rows.len(), 4);
+        assert_eq!(rows[0], (1704985180778, "a".to_string()));
+        assert_eq!(rows[1], (1704985181064, "b".to_string()));
+    }
+
+    #[test]
+    fn test_insert_records_as_json() {
+        let path = std::env::temp_dir().join(format!("r2logs-json-{}.db", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        assert_eq!(insert_records(&path, RECORDS, &[]).unwrap(), 2);
+
+        let connection = Connection::open(&path).unwrap();
+        let outcome = connection
+            .query_row(
+                "SELECT json_extract(json, '$.Outcome') FROM logs WHERE json_extract(json, '$.Event.RayID') = 'b'",
+                (),
+                |row| row.get::<_, String>(0),
+            )
+            .unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(outcome, "exception");
+    }
+}